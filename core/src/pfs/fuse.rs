@@ -0,0 +1,210 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License..
+
+//! Mount a [`SgxFile`] as a single-file FUSE filesystem.
+//!
+//! The adapter translates the VFS callbacks onto the existing
+//! `read_at`/`write_at`/`set_len`/`flush`/`metadata` surface so ordinary
+//! tools can edit an encrypted, integrity-checked file while the Merkle hash
+//! tree in `root_mht` is verified underneath every read. The encryption mode
+//! (`IntegrityOnly`, `UserKey`, …) is fixed at mount time via the wrapped
+//! [`SgxFile`].
+
+use std::ffi::OsStr;
+use std::time::{Duration, UNIX_EPOCH};
+
+use fuser::{
+    FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyEmpty, ReplyWrite, Request,
+};
+
+use crate::pfs::fs::SgxFile;
+use crate::pfs::sys::error::FsError;
+use crate::BlockSet;
+
+/// The single file is always inode 1; there are no other entries.
+const FILE_INODE: u64 = 1;
+const TTL: Duration = Duration::from_secs(1);
+
+/// A FUSE filesystem exposing one [`SgxFile`] at the mount root.
+pub struct ProtectedFileFs<D> {
+    file: SgxFile<D>,
+    uid: u32,
+    gid: u32,
+}
+
+impl<D: BlockSet> ProtectedFileFs<D> {
+    pub fn new(file: SgxFile<D>) -> Self {
+        // Safe defaults: the mounting process owns the file.
+        let uid = unsafe { libc::getuid() };
+        let gid = unsafe { libc::getgid() };
+        Self { file, uid, gid }
+    }
+
+    fn attr(&self) -> Result<FileAttr, FsError> {
+        let meta = self.file.metadata()?;
+        let mtime = UNIX_EPOCH + Duration::from_nanos(meta.mtime);
+        let ctime = UNIX_EPOCH + Duration::from_nanos(meta.ctime);
+        let atime = UNIX_EPOCH + Duration::from_nanos(meta.atime);
+        Ok(FileAttr {
+            ino: FILE_INODE,
+            size: meta.len,
+            blocks: meta.blocks,
+            atime,
+            mtime,
+            ctime,
+            crtime: ctime,
+            kind: FileType::RegularFile,
+            perm: 0o600,
+            nlink: 1,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: crate::BLOCK_SIZE as u32,
+            flags: 0,
+        })
+    }
+}
+
+// Map a filesystem error onto the closest POSIX errno for the FUSE reply.
+fn errno(err: FsError) -> i32 {
+    err.to_os_errno()
+}
+
+impl<D: BlockSet> Filesystem for ProtectedFileFs<D> {
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        if ino != FILE_INODE {
+            reply.error(libc::ENOENT);
+            return;
+        }
+        match self.attr() {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(errno(e)),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock: Option<u64>,
+        reply: ReplyData,
+    ) {
+        if ino != FILE_INODE {
+            reply.error(libc::ENOENT);
+            return;
+        }
+        let mut buf = vec![0u8; size as usize];
+        match self.file.read_at(&mut buf, offset as u64) {
+            Ok(n) => reply.data(&buf[..n]),
+            Err(e) => reply.error(errno(e)),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        if ino != FILE_INODE {
+            reply.error(libc::ENOENT);
+            return;
+        }
+        match self.file.write_at(data, offset as u64) {
+            Ok(n) => reply.written(n as u32),
+            Err(e) => reply.error(errno(e)),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<fuser::TimeOrNow>,
+        _mtime: Option<fuser::TimeOrNow>,
+        _ctime: Option<std::time::SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<std::time::SystemTime>,
+        _chgtime: Option<std::time::SystemTime>,
+        _bkuptime: Option<std::time::SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        if ino != FILE_INODE {
+            reply.error(libc::ENOENT);
+            return;
+        }
+        // Only truncation is meaningful for a single protected file.
+        if let Some(size) = size {
+            if let Err(e) = self.file.set_len(size) {
+                reply.error(errno(e));
+                return;
+            }
+        }
+        match self.attr() {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(errno(e)),
+        }
+    }
+
+    fn flush(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, _lock: u64, reply: ReplyEmpty) {
+        if ino != FILE_INODE {
+            reply.error(libc::ENOENT);
+            return;
+        }
+        match self.file.flush() {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(errno(e)),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _flags: i32,
+        _lock: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        match self.file.flush() {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(errno(e)),
+        }
+    }
+
+    fn lookup(&mut self, _req: &Request<'_>, _parent: u64, _name: &OsStr, reply: fuser::ReplyEntry) {
+        // The mount exposes exactly one file at a fixed inode; there is no
+        // directory tree to traverse.
+        reply.error(libc::ENOENT);
+    }
+}
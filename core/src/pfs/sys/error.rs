@@ -21,11 +21,16 @@ use crate::{impl_enum, Errno};
 pub type OsResult<T = ()> = core::result::Result<T, OsError>;
 pub type FsResult<T = ()> = core::result::Result<T, FsError>;
 pub const ENOENT: i32 = 2;
+pub const EIO: i32 = 5;
+pub const ENOMEM: i32 = 12;
 pub const EACCES: i32 = 13;
+pub const EBUSY: i32 = 16;
 pub const EINVAL: i32 = 22;
+pub const ENAMETOOLONG: i32 = 36;
 pub const EOPNOTSUPP: i32 = 95;
 pub const ENOTSUP: i32 = EOPNOTSUPP;
-pub const ENAMETOOLONG: i32 = 36;
+pub const ETIMEDOUT: i32 = 110;
+pub const EPERM: i32 = 1;
 
 impl_enum! {
     #[repr(u32)]
@@ -123,6 +128,32 @@ impl SgxStatus {
     pub fn is_success(&self) -> bool {
         *self == SgxStatus::Success
     }
+
+    /// The closest POSIX errno for this status. The file API only surfaces an
+    /// `SgxStatus` when there is no appropriate `EXXX`; this gives callers a
+    /// deterministic, filesystem-appropriate errno whenever one leaks out.
+    pub fn to_os_errno(&self) -> i32 {
+        match *self {
+            SgxStatus::MacMismatch
+            | SgxStatus::BadStatus
+            | SgxStatus::FluchFailed
+            | SgxStatus::CloseFailed
+            | SgxStatus::CantWriteRecoveryFile => EIO,
+            SgxStatus::NameMismatch
+            | SgxStatus::NotSgxFile
+            | SgxStatus::InvalidMetadata
+            | SgxStatus::InvalidSignature => EINVAL,
+            SgxStatus::NoPrivilege
+            | SgxStatus::InvalidAttribute
+            | SgxStatus::ServiceInvalidPrivilege => EACCES,
+            SgxStatus::OutOfMemory | SgxStatus::OutOfEPC | SgxStatus::MemoryMapFailure => ENOMEM,
+            SgxStatus::UnsupportedFeature | SgxStatus::UnsupportedConfig => EOPNOTSUPP,
+            SgxStatus::DeviceBusy | SgxStatus::ServiceBusy => EBUSY,
+            SgxStatus::ServiceTimeout => ETIMEDOUT,
+            SgxStatus::NoDevice | SgxStatus::EnclaveFileAccess => ENOENT,
+            _ => EINVAL,
+        }
+    }
 }
 
 impl SgxStatus {
@@ -211,6 +242,36 @@ impl SgxStatus {
         }
     }
 
+    /// Transient conditions worth retrying: a busy device/service, an AESM
+    /// timeout, a dropped network, or an `ENCLAVE_CREATE` interrupted by a
+    /// signal — the case the mainline SGX kernel driver retries on `EINTR`.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            *self,
+            SgxStatus::DeviceBusy
+                | SgxStatus::ServiceBusy
+                | SgxStatus::ServiceTimeout
+                | SgxStatus::EnclaveCreateInterrupted
+                | SgxStatus::NetworkFailure
+        )
+    }
+
+    /// File-status errors that call for running the recovery/clearerr path.
+    pub fn needs_recovery(&self) -> bool {
+        matches!(*self, SgxStatus::BadStatus | SgxStatus::RecoveryNeeded)
+    }
+
+    /// Decode a raw `sgx_status_t` word returned across an ecall/ocall.
+    /// Unrecognized values (newer SDKs keep adding codes) decode to
+    /// [`SgxStatus::Unexpected`]; use [`SgxStatus::try_from`] when the raw
+    /// code must be preserved.
+    pub fn from_raw(raw: u32) -> SgxStatus {
+        match SgxStatus::try_from(raw) {
+            Ok(status) => status,
+            Err(_) => SgxStatus::Unexpected,
+        }
+    }
+
     pub fn as_str(&self) -> &'static str {
         match *self {
             SgxStatus::Success => "Success.",
@@ -297,9 +358,109 @@ impl SgxStatus {
     }
 }
 
+impl TryFrom<u32> for SgxStatus {
+    type Error = u32;
+
+    /// Decode a raw `sgx_status_t`, returning the raw word unchanged in `Err`
+    /// when it is not a value this enum knows about. This keeps the FFI
+    /// boundary robust against forward-compatible SDKs instead of transmuting
+    /// an out-of-range discriminant (which would be UB).
+    fn try_from(raw: u32) -> Result<SgxStatus, u32> {
+        let status = match raw {
+            0x0000_0000 => SgxStatus::Success,
+            0x0000_0001 => SgxStatus::Unexpected,
+            0x0000_0002 => SgxStatus::InvalidParameter,
+            0x0000_0003 => SgxStatus::OutOfMemory,
+            0x0000_0004 => SgxStatus::EnclaveLost,
+            0x0000_0005 => SgxStatus::InvalidState,
+            0x0000_0008 => SgxStatus::UnsupportedFeature,
+            0x0000_0009 => SgxStatus::ThreadExit,
+            0x0000_000A => SgxStatus::MemoryMapFailure,
+            0x0000_1001 => SgxStatus::InvalidFunction,
+            0x0000_1003 => SgxStatus::OutOfTcs,
+            0x0000_1006 => SgxStatus::EnclaveCrashed,
+            0x0000_1007 => SgxStatus::ECallNotAllowed,
+            0x0000_1008 => SgxStatus::OCallNotAllowed,
+            0x0000_1009 => SgxStatus::StackOverRun,
+            0x0000_2000 => SgxStatus::UndefinedSymbol,
+            0x0000_2001 => SgxStatus::InvalidEnclave,
+            0x0000_2002 => SgxStatus::InvalidEcnalveId,
+            0x0000_2003 => SgxStatus::InvalidSignature,
+            0x0000_2004 => SgxStatus::NotDebugEnclave,
+            0x0000_2005 => SgxStatus::OutOfEPC,
+            0x0000_2006 => SgxStatus::NoDevice,
+            0x0000_2007 => SgxStatus::MemoryMapConflict,
+            0x0000_2009 => SgxStatus::InvalidMetadata,
+            0x0000_200C => SgxStatus::DeviceBusy,
+            0x0000_200D => SgxStatus::InvalidVersion,
+            0x0000_200E => SgxStatus::ModeIncompatible,
+            0x0000_200F => SgxStatus::EnclaveFileAccess,
+            0x0000_2010 => SgxStatus::InvalidMisc,
+            0x0000_2011 => SgxStatus::InvalidLaunchToken,
+            0x0000_3001 => SgxStatus::MacMismatch,
+            0x0000_3002 => SgxStatus::InvalidAttribute,
+            0x0000_3003 => SgxStatus::InvalidCpusvn,
+            0x0000_3004 => SgxStatus::InvalidIsvsvn,
+            0x0000_3005 => SgxStatus::InvalidKeyname,
+            0x0000_4001 => SgxStatus::ServiceUnavailable,
+            0x0000_4002 => SgxStatus::ServiceTimeout,
+            0x0000_4003 => SgxStatus::InvalidEpidBlob,
+            0x0000_4004 => SgxStatus::ServiceInvalidPrivilege,
+            0x0000_4005 => SgxStatus::EpidMemoryRevoked,
+            0x0000_4006 => SgxStatus::UpdateNeeded,
+            0x0000_4007 => SgxStatus::NetworkFailure,
+            0x0000_4008 => SgxStatus::InvalidAeSession,
+            0x0000_400A => SgxStatus::ServiceBusy,
+            0x0000_400C => SgxStatus::McNotFound,
+            0x0000_400D => SgxStatus::McNoAccess,
+            0x0000_400E => SgxStatus::McUsedUp,
+            0x0000_400F => SgxStatus::McOverQuota,
+            0x0000_4011 => SgxStatus::KdfMismatch,
+            0x0000_4012 => SgxStatus::UnrecognizedPlatform,
+            0x0000_4013 => SgxStatus::UnsupportedConfig,
+            0x0000_5002 => SgxStatus::NoPrivilege,
+            0x0000_6001 => SgxStatus::PclEncrypted,
+            0x0000_6002 => SgxStatus::PclNotEncrypted,
+            0x0000_6003 => SgxStatus::PclMacMismatch,
+            0x0000_6004 => SgxStatus::PclShaMismatch,
+            0x0000_6005 => SgxStatus::PclGuidMismatch,
+            0x0000_7001 => SgxStatus::BadStatus,
+            0x0000_7002 => SgxStatus::NoKeyId,
+            0x0000_7003 => SgxStatus::NameMismatch,
+            0x0000_7004 => SgxStatus::NotSgxFile,
+            0x0000_7005 => SgxStatus::CantOpenRecoveryFile,
+            0x0000_7006 => SgxStatus::CantWriteRecoveryFile,
+            0x0000_7007 => SgxStatus::RecoveryNeeded,
+            0x0000_7008 => SgxStatus::FluchFailed,
+            0x0000_7009 => SgxStatus::CloseFailed,
+            0x0000_8001 => SgxStatus::UnsupportedAttKeyid,
+            0x0000_8002 => SgxStatus::AttKeyCertFailed,
+            0x0000_8003 => SgxStatus::AttKeyUninitialized,
+            0x0000_8004 => SgxStatus::InvaliedAttKeyCertData,
+            0x0000_8005 => SgxStatus::INvaliedPlatfromCert,
+            0x0000_F001 => SgxStatus::EnclaveCreateInterrupted,
+            other => return Err(other),
+        };
+        Ok(status)
+    }
+}
+
+/// Call-site context attached to an SGX failure by [`try_sgx!`], modeled on
+/// the SGX `CHECK_STATUS` throw-with-function-and-line pattern. Holds only
+/// `'static` strings and a line number, so it allocates nothing and is usable
+/// from `no_std`/enclave contexts without an unwinding runtime.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SgxContext {
+    pub file: &'static str,
+    pub line: u32,
+    pub op: &'static str,
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum FsError {
     SgxError(SgxStatus),
+    /// An SGX failure enriched with the originating file/line/operation.
+    SgxErrorWithCtx(SgxStatus, SgxContext),
     OsError(i32),
     Errno(crate::error::Error),
 }
@@ -315,9 +476,26 @@ impl FsError {
         FsError::OsError(errno)
     }
 
+    #[inline]
+    pub fn from_sgx_error_with_ctx(errno: SgxStatus, ctx: SgxContext) -> Self {
+        FsError::SgxErrorWithCtx(errno, ctx)
+    }
+
+    /// Build an error from a raw `sgx_status_t` word. A status this build does
+    /// not recognize is preserved verbatim as an [`FsError::OsError`] so the
+    /// original code is not lost, mirroring how the raw word decodes to
+    /// [`SgxStatus::Unexpected`] in [`SgxStatus::from_raw`].
+    #[inline]
+    pub fn from_raw_sgx_error(raw: u32) -> Self {
+        match SgxStatus::try_from(raw) {
+            Ok(status) => FsError::SgxError(status),
+            Err(raw) => FsError::OsError(raw as i32),
+        }
+    }
+
     #[inline]
     pub fn equal_to_sgx_error(&self, other: SgxStatus) -> bool {
-        matches!(self, FsError::SgxError(e) if *e == other)
+        matches!(self, FsError::SgxError(e) | FsError::SgxErrorWithCtx(e, _) if *e == other)
     }
 
     #[allow(dead_code)]
@@ -329,7 +507,7 @@ impl FsError {
     #[inline]
     pub fn is_success(&self) -> bool {
         match self {
-            Self::SgxError(status) => status.is_success(),
+            Self::SgxError(status) | Self::SgxErrorWithCtx(status, _) => status.is_success(),
             Self::OsError(errno) => *errno == 0,
             Self::Errno(_) => false,
         }
@@ -351,17 +529,81 @@ impl FsError {
     #[allow(dead_code)]
     pub fn to_errno(self) -> crate::Error {
         match self {
-            Self::SgxError(status) => crate::Error::with_msg(Errno::SgxError, status.as_str()),
+            Self::SgxError(status) | Self::SgxErrorWithCtx(status, _) => {
+                crate::Error::with_msg(Errno::SgxError, status.as_str())
+            }
             Self::OsError(errno) => crate::Error::from(errno),
             Self::Errno(errno) => crate::Error::from(errno),
         }
     }
+
+    /// Whether this error reflects a transient condition worth retrying. Only
+    /// SGX-status errors can be transient; see [`SgxStatus::is_transient`].
+    #[inline]
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::SgxError(status) | Self::SgxErrorWithCtx(status, _) => status.is_transient(),
+            _ => false,
+        }
+    }
+
+    /// Whether this error calls for running the recovery path; see
+    /// [`SgxStatus::needs_recovery`].
+    #[inline]
+    pub fn needs_recovery(&self) -> bool {
+        match self {
+            Self::SgxError(status) | Self::SgxErrorWithCtx(status, _) => status.needs_recovery(),
+            _ => false,
+        }
+    }
+
+    /// The closest POSIX errno for this error, mapping SGX-specific statuses
+    /// through [`SgxStatus::to_os_errno`].
+    #[allow(dead_code)]
+    pub fn to_os_errno(&self) -> i32 {
+        match self {
+            Self::SgxError(status) | Self::SgxErrorWithCtx(status, _) => status.to_os_errno(),
+            Self::OsError(errno) => *errno,
+            Self::Errno(errno) => errno.errno() as i32,
+        }
+    }
+}
+
+/// Re-invoke `op` while it fails with a transient error, up to `max_attempts`
+/// total tries, returning the first success or the last (non-transient or
+/// exhausted) result. This is the single place the filesystem handles flaky
+/// enclave/AESM conditions instead of open-coding status comparisons.
+pub fn with_retry<T, F>(mut op: F, max_attempts: u32) -> FsResult<T>
+where
+    F: FnMut() -> FsResult<T>,
+{
+    let attempts = max_attempts.max(1);
+    let mut result = op();
+    let mut attempt = 1;
+    while attempt < attempts {
+        match &result {
+            Err(e) if e.is_transient() => {
+                result = op();
+                attempt += 1;
+            }
+            _ => break,
+        }
+    }
+    result
 }
 
 impl fmt::Display for FsError {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::SgxError(status) => write!(fmt, "sgx error {}", status.as_str()),
+            Self::SgxErrorWithCtx(status, ctx) => write!(
+                fmt,
+                "sgx error {} at {}:{} (in {})",
+                status.as_str(),
+                ctx.file,
+                ctx.line,
+                ctx.op
+            ),
             Self::OsError(errno) => write!(fmt, "os error {}", errno),
             Self::Errno(errno) => write!(fmt, "errno {}", errno),
         }
@@ -374,6 +616,62 @@ impl From<SgxStatus> for FsError {
     }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for FsError {}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for FsError {
+    fn from(err: std::io::Error) -> FsError {
+        // Prefer the raw OS errno so a round-trip through io::Error is lossless;
+        // fall back to the crate error for synthetic io errors without one.
+        match err.raw_os_error() {
+            Some(errno) => FsError::OsError(errno),
+            None => FsError::Errno(crate::Error::with_msg(Errno::IoFailed, "io error")),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<FsError> for std::io::Error {
+    fn from(err: FsError) -> std::io::Error {
+        use std::io::ErrorKind;
+        let kind = match err {
+            FsError::SgxError(status) | FsError::SgxErrorWithCtx(status, _) => {
+                status.io_error_kind()
+            }
+            FsError::OsError(errno) => return std::io::Error::from_raw_os_error(errno),
+            FsError::Errno(_) => ErrorKind::Other,
+        };
+        std::io::Error::new(kind, err.to_string())
+    }
+}
+
+#[cfg(feature = "std")]
+impl SgxStatus {
+    /// The closest `std::io::ErrorKind` for this status, used by the
+    /// `From<FsError> for io::Error` conversion. The `SgxStatus::as_str()`
+    /// text is preserved as the error message by the caller.
+    pub fn io_error_kind(&self) -> std::io::ErrorKind {
+        use std::io::ErrorKind;
+        match *self {
+            SgxStatus::MacMismatch
+            | SgxStatus::NameMismatch
+            | SgxStatus::NotSgxFile
+            | SgxStatus::InvalidMetadata => ErrorKind::InvalidData,
+            SgxStatus::CantOpenRecoveryFile
+            | SgxStatus::EnclaveFileAccess
+            | SgxStatus::NoDevice => ErrorKind::NotFound,
+            SgxStatus::NoPrivilege
+            | SgxStatus::InvalidAttribute
+            | SgxStatus::ServiceInvalidPrivilege => ErrorKind::PermissionDenied,
+            SgxStatus::OutOfMemory | SgxStatus::OutOfEPC | SgxStatus::MemoryMapFailure => {
+                ErrorKind::OutOfMemory
+            }
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
 #[macro_export]
 macro_rules! esgx {
     ($status:expr) => {
@@ -381,9 +679,97 @@ macro_rules! esgx {
     };
 }
 
+mod tests {
+    use super::{with_retry, FsError, SgxStatus, EBUSY, EINVAL, ENOMEM};
+
+    #[test]
+    fn raw_roundtrip() {
+        assert_eq!(SgxStatus::try_from(0x0000_3001), Ok(SgxStatus::MacMismatch));
+        assert_eq!(SgxStatus::try_from(0x0000_DEAD), Err(0x0000_DEAD));
+        assert_eq!(SgxStatus::from_raw(0x0000_DEAD), SgxStatus::Unexpected);
+    }
+
+    #[test]
+    fn errno_mapping() {
+        assert_eq!(SgxStatus::MacMismatch.to_os_errno(), super::EIO);
+        assert_eq!(SgxStatus::OutOfMemory.to_os_errno(), ENOMEM);
+        assert_eq!(SgxStatus::DeviceBusy.to_os_errno(), EBUSY);
+        assert_eq!(SgxStatus::NoKeyId.to_os_errno(), EINVAL);
+    }
+
+    #[test]
+    fn retry_stops_on_non_transient() {
+        let mut calls = 0;
+        let result: super::FsResult<()> = with_retry(
+            || {
+                calls += 1;
+                Err(FsError::SgxError(SgxStatus::MacMismatch))
+            },
+            4,
+        );
+        assert_eq!(calls, 1);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn retry_exhausts_on_transient() {
+        let mut calls = 0;
+        let result: super::FsResult<()> = with_retry(
+            || {
+                calls += 1;
+                Err(FsError::SgxError(SgxStatus::ServiceBusy))
+            },
+            3,
+        );
+        assert_eq!(calls, 3);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn retry_returns_eventual_success() {
+        let mut calls = 0;
+        let result = with_retry(
+            || {
+                calls += 1;
+                if calls < 2 {
+                    Err(FsError::SgxError(SgxStatus::DeviceBusy))
+                } else {
+                    Ok(calls)
+                }
+            },
+            5,
+        );
+        assert_eq!(result, Ok(2));
+    }
+}
+
 #[macro_export]
 macro_rules! eos {
     ($errno:expr) => {
         $crate::pfs::sys::error::FsError::from_os_error($errno)
     };
 }
+
+/// Check an [`SgxStatus`] and, on anything other than `Success`, return early
+/// with an [`FsError::SgxErrorWithCtx`] stamped with the call site and a short
+/// operation label. Mirrors the SGX runtime's `CHECK_STATUS(op)` helper so a
+/// MAC-mismatch or bad-metadata failure carries where it was raised rather
+/// than just the bare status. Captures nothing but `'static` strings.
+#[macro_export]
+macro_rules! try_sgx {
+    ($status:expr, $op:expr) => {{
+        let status = $status;
+        if !status.is_success() {
+            return ::core::result::Result::Err(
+                $crate::pfs::sys::error::FsError::from_sgx_error_with_ctx(
+                    status,
+                    $crate::pfs::sys::error::SgxContext {
+                        file: file!(),
+                        line: line!(),
+                        op: $op,
+                    },
+                ),
+            );
+        }
+    }};
+}
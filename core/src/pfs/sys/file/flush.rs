@@ -0,0 +1,167 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License..
+
+use crate::pfs::sys::error::FsResult;
+use crate::pfs::sys::file::FileInner;
+use crate::pfs::sys::host::{HostFs, RECOVERY_NODE_SIZE};
+use crate::pfs::sys::node::NODE_SIZE;
+use crate::BlockSet;
+
+impl<D: BlockSet> FileInner<D> {
+    /// Flush all dirty nodes to the host file as a single crash-consistent
+    /// commit.
+    ///
+    /// The commit is bracketed by the metadata `update_flag` and a recovery
+    /// journal holding the *pre-write* image of every block about to change,
+    /// so an interruption at any point leaves the file restorable to its last
+    /// consistent state (see [`FileInner::open`] for the replay side). The
+    /// ordering barriers below are the whole point of the scheme:
+    ///
+    /// 1. journal the pre-images and fsync the journal,
+    /// 2. set `update_flag = 1` and fsync the metadata block,
+    /// 3. write every dirty data/MHT node and fsync,
+    /// 4. clear `update_flag` and fsync the metadata block again,
+    /// 5. drop the now-obsolete journal.
+    pub fn flush(&mut self) -> FsResult {
+        // Inside an open batch, defer the commit: dirty nodes accumulate in the
+        // cache and are sealed as one group by [`commit_batch`](Self::commit_batch).
+        if self.in_batch || !self.need_writing {
+            return Ok(());
+        }
+
+        self.touch_mtime();
+
+        // 1. capture the pre-write ciphertext of every block we are about to
+        //    overwrite, including the metadata/root-MHT block, seal them as a
+        //    single commit group, and make the journal durable before touching
+        //    the main file.
+        let dirty = self.collect_dirty_physical_numbers()?;
+        for &physical_number in dirty.iter() {
+            self.journal_pre_image(physical_number)?;
+        }
+        self.journal.commit()?;
+        self.journal.flush()?;
+
+        // 2. flag-on before data: a crash from here on is detected on reopen.
+        self.metadata.set_update_flag(1);
+        self.metadata.write_to_disk(&mut self.host_file)?;
+        self.host_file.flush()?;
+
+        // 3. write the dirty nodes (MAC chain already recomputed up to
+        //    root_mht by the caller) and make them durable.
+        self.write_dirty_nodes()?;
+        self.host_file.flush()?;
+
+        // 4. flag-off after data: the file is now consistent again.
+        self.metadata.set_update_flag(0);
+        self.metadata.write_to_disk(&mut self.host_file)?;
+        self.host_file.flush()?;
+
+        // 5. flag-off before recovery truncation: only now may the journal go.
+        self.journal.reset()?;
+
+        self.need_writing = false;
+        Ok(())
+    }
+
+    /// Open a batched, transactional commit.
+    ///
+    /// Until [`commit_batch`](Self::commit_batch), every [`flush`](Self::flush)
+    /// — including the implicit ones the write path issues when the cache fills
+    /// — becomes a no-op, so writes spanning many nodes coalesce into a single
+    /// commit group. This amortizes the per-commit fsync cost and gives
+    /// all-or-nothing semantics to a multi-write operation.
+    pub fn begin_batch(&mut self) {
+        self.in_batch = true;
+    }
+
+    /// Commit every node dirtied since [`begin_batch`](Self::begin_batch) as one
+    /// crash-atomic group: all pre-images are journaled and sealed with a single
+    /// `commit`, and the metadata `update_flag` barrier is flipped once for the
+    /// whole batch. On reopen, recovery replays or rolls back the entire group
+    /// as a unit — either all batched updates survive a crash or none do.
+    pub fn commit_batch(&mut self) -> FsResult {
+        self.in_batch = false;
+        self.flush()
+    }
+
+    /// Leave an open batch without committing it. The dirty nodes stay in the
+    /// cache and no journal record has been written for them; the next
+    /// [`flush`](Self::flush) commits them.
+    pub fn abort_batch(&mut self) {
+        self.in_batch = false;
+    }
+
+    /// Collect, in ascending physical order, the block numbers of every node
+    /// that carries unwritten changes — the dirty data/MHT nodes resident in
+    /// the LRU cache plus the root MHT. Metadata is committed separately as
+    /// part of the `update_flag` barrier.
+    fn collect_dirty_physical_numbers(&self) -> FsResult<Vec<u64>> {
+        let mut dirty = Vec::new();
+        {
+            let root_mht = self.root_mht.borrow();
+            if root_mht.need_writing {
+                dirty.push(root_mht.physical_number);
+            }
+        }
+        for node in self.cache.iter() {
+            let node = node.borrow();
+            if node.need_writing {
+                dirty.push(node.physical_number);
+            }
+        }
+        dirty.sort_unstable();
+        dirty.dedup();
+        Ok(dirty)
+    }
+
+    /// Write every dirty node's ciphertext to the host file, clearing its
+    /// `need_writing` marker. The MAC chain up to `root_mht` is recomputed by
+    /// the write path before `flush` is called, so here we only persist.
+    fn write_dirty_nodes(&mut self) -> FsResult {
+        for node in self.cache.iter() {
+            let mut node = node.borrow_mut();
+            if !node.need_writing {
+                continue;
+            }
+            let physical_number = node.physical_number;
+            self.host_file
+                .write(physical_number, node.ciphertext.node_data.data.as_ref())?;
+            node.need_writing = false;
+            node.new_node = false;
+        }
+        let mut root_mht = self.root_mht.borrow_mut();
+        if root_mht.need_writing {
+            self.host_file
+                .write(root_mht.physical_number, root_mht.ciphertext.node_data.data.as_ref())?;
+            root_mht.need_writing = false;
+            root_mht.new_node = false;
+        }
+        Ok(())
+    }
+
+    /// Append the current on-disk ciphertext of `physical_number` to the
+    /// recovery journal as a `{physical_block_number, original_ciphertext}`
+    /// record, so the block can be rolled back if the commit is interrupted.
+    fn journal_pre_image(&mut self, physical_number: u64) -> FsResult {
+        let mut record = vec![0u8; RECOVERY_NODE_SIZE];
+        record[0..8].copy_from_slice(&physical_number.to_ne_bytes());
+        self.host_file
+            .read(physical_number, &mut record[8..8 + NODE_SIZE])?;
+        self.journal.append(&record)
+    }
+}
@@ -21,38 +21,42 @@ use crate::eos;
 use crate::pfs::sgx::KeyPolicy;
 use crate::pfs::sys::cache::LruCache;
 use crate::pfs::sys::error::{FsError, FsResult};
+use crate::pfs::sys::host::block_file::BlockFile;
+use crate::pfs::sys::host::journal::RecoveryJournal;
 use crate::pfs::sys::keys::FsKeyGen;
 use crate::pfs::sys::metadata::MetadataInfo;
 use crate::pfs::sys::node::{FileNode, FileNodeRef};
 use crate::pfs::sys::EncryptMode;
 use crate::AeadKey;
 use crate::AeadMac;
+use crate::BlockSet;
 
 use std::io::SeekFrom;
 use std::path::Path;
-use std::path::PathBuf;
 use std::sync::Mutex;
 
 use super::error::SgxStatus;
 use super::error::EINVAL;
-use super::host::HostFile;
 
 mod close;
 mod flush;
 mod node;
 mod open;
 mod other;
+mod parallel;
 mod read;
 mod write;
 
+pub use open::{CorruptNode, Docket, JournalReservation, LayoutConfig, ScrubReport};
+
 #[derive(Debug)]
-pub struct ProtectedFile {
-    file: Mutex<FileInner>,
+pub struct ProtectedFile<D> {
+    file: Mutex<FileInner<D>>,
 }
 
 #[derive(Debug)]
-struct FileInner {
-    host_file: HostFile,
+struct FileInner<D> {
+    host_file: BlockFile<D>,
     metadata: MetadataInfo,
     root_mht: FileNodeRef,
     key_gen: FsKeyGen,
@@ -63,18 +67,49 @@ struct FileInner {
     offset: usize,
     last_error: FsError,
     status: FileStatus,
-    recovery_path: PathBuf,
+    // Pre-image log replayed on the next open if a commit is interrupted; see
+    // [`FileInner::flush`].
+    journal: RecoveryJournal<D>,
+    // Number of AEAD worker threads requested for multi-node crypto, clamped
+    // to at least 1 by `FileInner::resolve_workers`. Not yet dispatched by
+    // the read/write path — see `parallel::resolve_workers`'s doc comment.
+    workers: usize,
     cache: LruCache<FileNode>,
+    // Whether the root MHT subtree has been read and verified. Opens leave it
+    // `false` for files that carry an on-disk MHT and materialize it lazily on
+    // first access; see [`FileInner::ensure_root_loaded`].
+    root_loaded: bool,
+    // Set between `begin_batch` and `commit_batch`: suppresses per-write
+    // flushes so a multi-node operation commits as one group.
+    in_batch: bool,
 }
 
-impl ProtectedFile {
-    pub fn open<P: AsRef<Path>>(
-        path: P,
+impl<D: BlockSet> ProtectedFile<D> {
+    pub fn open(
+        disk: D,
+        path: &str,
+        opts: &OpenOptions,
+        mode: &OpenMode,
+        cache_size: Option<usize>,
+        workers: usize,
+        layout: Option<LayoutConfig>,
+    ) -> FsResult<Self> {
+        let file = FileInner::open(path, disk, opts, mode, cache_size, workers, layout)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn create(
+        disk: D,
+        path: &str,
         opts: &OpenOptions,
         mode: &OpenMode,
         cache_size: Option<usize>,
+        workers: usize,
+        layout: Option<LayoutConfig>,
     ) -> FsResult<Self> {
-        let file = FileInner::open(path.as_ref(), opts, mode, cache_size)?;
+        let file = FileInner::create(path, disk, opts, mode, cache_size, workers, layout)?;
         Ok(Self {
             file: Mutex::new(file),
         })
@@ -271,37 +306,266 @@ impl ProtectedFile {
             })
     }
 
+    /// Re-encrypt the file in place under `new_mode`.
+    ///
+    /// Every resident [`FileNode`] is decrypted with the current derived keys,
+    /// re-encrypted under keys re-derived from the new master key, and the MAC
+    /// chain is recomputed up to `root_mht` before the new metadata is
+    /// committed through the recovery-file mechanism, so a crash mid-rotation
+    /// rolls back to the pre-rotation state. This supports periodic key
+    /// rolling and migrating e.g. `AutoKey(MRSIGNER)` to `UserKey` without a
+    /// second file.
+    pub fn rotate_key(&self, new_mode: &OpenMode) -> FsResult {
+        let mut file = self.file.lock().map_err(|posion_error| {
+            let mut file = posion_error.into_inner();
+            file.set_last_error(SgxStatus::Unexpected);
+            file.set_file_status(FileStatus::MemoryCorrupted);
+            SgxStatus::Unexpected
+        })?;
+        file.rotate_key(new_mode).map_err(|error| {
+            file.set_last_error(error);
+            error
+        })
+    }
+
+    /// Unwrap and return the per-file node key from the metadata node.
+    pub fn export_metadata_key(&self) -> FsResult<AeadKey> {
+        let file = self.file.lock().map_err(|posion_error| {
+            let mut file = posion_error.into_inner();
+            file.set_last_error(SgxStatus::Unexpected);
+            file.set_file_status(FileStatus::MemoryCorrupted);
+            SgxStatus::Unexpected
+        })?;
+        file.export_metadata_key()
+    }
+
+    /// Re-wrap the per-file node key under `new_root`, updating the metadata
+    /// MAC. The data nodes are left untouched.
+    pub fn import_metadata_key(&self, new_root: AeadKey) -> FsResult<()> {
+        let mut file = self.file.lock().map_err(|posion_error| {
+            let mut file = posion_error.into_inner();
+            file.set_last_error(SgxStatus::Unexpected);
+            file.set_file_status(FileStatus::MemoryCorrupted);
+            SgxStatus::Unexpected
+        })?;
+        file.import_metadata_key(new_root).map_err(|error| {
+            file.set_last_error(error);
+            error
+        })
+    }
+
     pub fn remove<P: AsRef<Path>>(path: P) -> FsResult {
         FileInner::remove(path.as_ref())
     }
 
+    /// Open a batched commit spanning several writes.
+    ///
+    /// Writes issued between `begin_batch` and [`Self::commit_batch`] do not
+    /// flush individually; they are committed together as one crash-atomic
+    /// group, so either all of them survive a crash or none do. See
+    /// [`FileInner::begin_batch`].
+    pub fn begin_batch(&self) -> FsResult {
+        let mut file = self.file.lock().map_err(|posion_error| {
+            let mut file = posion_error.into_inner();
+            file.set_last_error(SgxStatus::Unexpected);
+            file.set_file_status(FileStatus::MemoryCorrupted);
+            SgxStatus::Unexpected
+        })?;
+        file.begin_batch();
+        Ok(())
+    }
+
+    /// Commit the writes accumulated since [`Self::begin_batch`] as one group.
+    pub fn commit_batch(&self) -> FsResult {
+        let mut file = self.file.lock().map_err(|posion_error| {
+            let mut file = posion_error.into_inner();
+            file.set_last_error(SgxStatus::Unexpected);
+            file.set_file_status(FileStatus::MemoryCorrupted);
+            SgxStatus::Unexpected
+        })?;
+        file.commit_batch().map_err(|error| {
+            file.set_last_error(error);
+            error
+        })
+    }
+
+    /// Abandon the open batch without committing; see [`FileInner::abort_batch`].
+    pub fn abort_batch(&self) -> FsResult {
+        let mut file = self.file.lock().map_err(|posion_error| {
+            let mut file = posion_error.into_inner();
+            file.set_last_error(SgxStatus::Unexpected);
+            file.set_file_status(FileStatus::MemoryCorrupted);
+            SgxStatus::Unexpected
+        })?;
+        file.abort_batch();
+        Ok(())
+    }
+
+    /// Return the stat-style metadata tracked inside the encrypted header.
+    ///
+    /// The timestamps and size live in the *encrypted* part of the metadata
+    /// node (see `MetadataInfo::encrypted_plain`) so they are neither readable
+    /// nor forgeable on the host, and they are covered by the same MAC as
+    /// [`Self::get_metadata_mac`].
+    pub fn metadata(&self) -> FsResult<FileMetadata> {
+        let file = self.file.lock().map_err(|posion_error| {
+            let mut file = posion_error.into_inner();
+            file.set_last_error(SgxStatus::Unexpected);
+            file.set_file_status(FileStatus::MemoryCorrupted);
+            SgxStatus::Unexpected
+        })?;
+        Ok(file.metadata_stat())
+    }
+
     #[cfg(feature = "tfs")]
-    pub fn export_key<P: AsRef<Path>>(path: P) -> FsResult<Key128bit> {
+    pub fn export_key<P: AsRef<Path>>(disk: D, path: P) -> FsResult<Key128bit> {
+        let path = path.as_ref().to_str().ok_or_else(|| eos!(EINVAL))?;
         let mut file = FileInner::open(
-            path.as_ref(),
+            path,
+            disk,
             &OpenOptions::new().read(true),
             &OpenMode::ExportKey,
             None,
+            1,
+            None,
         )?;
         file.close(CloseMode::Export).map(|key| key.unwrap())
     }
 
     #[cfg(feature = "tfs")]
     pub fn import_key<P: AsRef<Path>>(
+        disk: D,
         path: P,
         key: Key128bit,
         key_policy: Option<KeyPolicy>,
     ) -> FsResult {
+        let path = path.as_ref().to_str().ok_or_else(|| eos!(EINVAL))?;
         let mut file = FileInner::open(
-            path.as_ref(),
+            path,
+            disk,
             &OpenOptions::new().read(true).update(true),
             &OpenMode::ImportKey((key, key_policy.unwrap_or(KeyPolicy::MRSIGNER))),
             None,
+            1,
+            None,
         )?;
         file.close(CloseMode::Import).map(|_| ())
     }
 }
 
+/// Stat-style view of a protected file, mirroring the `st_mtime_nsec` /
+/// `st_ctime_nsec` / `st_blocks` surface exposed by `std::os::unix::fs::
+/// MetadataExt`.
+///
+/// All fields are read from the encrypted metadata header; `atime`, `mtime`
+/// and `ctime` are nanoseconds since the Unix epoch.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct FileMetadata {
+    pub len: u64,
+    pub blocks: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+impl<D: BlockSet> FileInner<D> {
+    /// In-place key rotation; see [`ProtectedFile::rotate_key`].
+    pub(crate) fn rotate_key(&mut self, new_mode: &OpenMode) -> FsResult {
+        new_mode.check()?;
+        let new_key_gen = FsKeyGen::new(new_mode)?;
+
+        // Materialize and decrypt every node with the current keys so the full
+        // plaintext subtree is resident before we re-key; re-encryption then
+        // happens purely in memory and is made durable by a single commit.
+        self.load_all_nodes()?;
+        for node in self.cache.iter() {
+            let mut node = node.borrow_mut();
+            let gcm = node.get_gcm_data()?;
+            node.decrypt(&gcm.key, &gcm.mac)?;
+        }
+
+        // Swap in the new derivation and encryption policy, then re-encrypt the
+        // whole tree bottom-up so every MHT GMAC is recomputed up to the root.
+        self.key_gen = new_key_gen;
+        self.metadata.set_encrypt_flags(new_mode.into());
+        if let Some(policy) = new_mode.key_policy() {
+            self.metadata.set_key_policy(policy);
+        }
+        let root_key = self.metadata.restore_key(&self.key_gen)?;
+        for node in self.cache.iter() {
+            let mut node = node.borrow_mut();
+            node.encrypt_flags = self.metadata.encrypt_flags();
+            node.need_writing = true;
+            node.encrypt(&root_key)?;
+        }
+        let mac = self.root_mht.borrow_mut().encrypt(&root_key)?;
+        self.metadata.encrypted_plain.mht_gmac = mac;
+        self.metadata.encrypted_plain.mht_key = root_key;
+
+        self.need_writing = true;
+        self.touch_mtime();
+        self.flush()
+    }
+
+    /// Unwrap and return the per-file node key from the metadata node; see
+    /// [`ProtectedFile::export_metadata_key`].
+    pub(crate) fn export_metadata_key(&self) -> FsResult<AeadKey> {
+        Ok(self.metadata.encrypted_plain.mht_key)
+    }
+
+    /// Re-wrap the per-file node key under `new_root`, updating the metadata
+    /// MAC; see [`ProtectedFile::import_metadata_key`].
+    ///
+    /// Only the root MHT is re-encrypted: every node below it keeps its own
+    /// independent key (stored, encrypted, inside its parent), so re-wrapping
+    /// the root's key never requires touching a single data node.
+    pub(crate) fn import_metadata_key(&mut self, new_root: AeadKey) -> FsResult<()> {
+        self.ensure_root_loaded()?;
+        let mac = self.root_mht.borrow_mut().encrypt(&new_root)?;
+        self.metadata.encrypted_plain.mht_gmac = mac;
+        self.metadata.encrypted_plain.mht_key = new_root;
+
+        self.need_writing = true;
+        self.touch_mtime();
+        self.flush()
+    }
+
+    /// Snapshot the encrypted stat fields into a [`FileMetadata`].
+    pub(crate) fn metadata_stat(&self) -> FileMetadata {
+        let plain = &self.metadata.encrypted_plain;
+        FileMetadata {
+            len: plain.size as u64,
+            blocks: plain.blocks,
+            atime: plain.atime,
+            mtime: plain.mtime,
+            ctime: plain.ctime,
+        }
+    }
+
+    /// Record a read access. Called from the `read`/`read_at` paths.
+    pub(crate) fn touch_atime(&mut self) {
+        self.metadata.encrypted_plain.atime = now_nanos();
+    }
+
+    /// Record a data/metadata change. Called from the `write`/`set_len`/
+    /// `flush` paths; also advances `ctime`.
+    pub(crate) fn touch_mtime(&mut self) {
+        let now = now_nanos();
+        self.metadata.encrypted_plain.mtime = now;
+        self.metadata.encrypted_plain.ctime = now;
+    }
+}
+
+/// Nanoseconds since the Unix epoch, saturating to 0 before 1970 and on a
+/// broken host clock so metadata updates never panic inside an enclave.
+fn now_nanos() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
 #[allow(dead_code)]
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum FileStatus {
@@ -505,10 +769,13 @@ pub enum CloseMode {
 mod test {
     use std::sync::Once;
 
-    use crate::pfs::sys::host::HostFs;
+    use crate::layers::bio::MemDisk;
+    use crate::pfs::sys::host::{HostFile, HostFs};
 
     use super::*;
 
+    const TEST_DISK_BLOCKS: usize = 64;
+
     static INIT_LOG: Once = Once::new();
 
     fn init_logger() {
@@ -523,14 +790,16 @@ mod test {
 
     #[test]
     fn simple_read_write() {
-        let file_path = Path::new("test.data");
-        let _ = std::fs::File::create(file_path).unwrap();
+        let disk = MemDisk::create(TEST_DISK_BLOCKS).unwrap();
         let opts = OpenOptions::new().read(false).write(true).append(false);
-        let file = ProtectedFile::open(
-            file_path,
+        let file = ProtectedFile::create(
+            disk.clone(),
+            "test.data",
             &opts,
             &OpenMode::UserKey(AeadKey::default()),
             None,
+            1,
+            None,
         )
         .unwrap();
         file.write(b"hello").unwrap();
@@ -539,10 +808,13 @@ mod test {
         drop(file);
         let opts = OpenOptions::new().read(true).write(false).append(false);
         let file = ProtectedFile::open(
-            file_path,
+            disk,
+            "test.data",
             &opts,
             &OpenMode::UserKey(AeadKey::default()),
             None,
+            1,
+            None,
         )
         .unwrap();
         let mut read_buffer = vec![0u8; 5];
@@ -599,12 +871,20 @@ mod test {
     #[test]
     fn multiple_block_write() {
         init_logger();
-        let file_path = Path::new("test.data");
-        let _ = std::fs::File::create(file_path).unwrap();
+        let disk = MemDisk::create(TEST_DISK_BLOCKS).unwrap();
 
         //  let key = AeadKey::default();
         let opts = OpenOptions::new().read(false).write(false).append(true);
-        let file = ProtectedFile::open(file_path, &opts, &OpenMode::IntegrityOnly, None).unwrap();
+        let file = ProtectedFile::create(
+            disk.clone(),
+            "test.data",
+            &opts,
+            &OpenMode::IntegrityOnly,
+            None,
+            1,
+            None,
+        )
+        .unwrap();
 
         let block_size = 4 * 1024;
         let block_number = 1;
@@ -615,7 +895,16 @@ mod test {
         file.flush().unwrap();
 
         let opts = OpenOptions::new().read(true).write(false).append(false);
-        let file = ProtectedFile::open(file_path, &opts, &OpenMode::IntegrityOnly, None).unwrap();
+        let file = ProtectedFile::open(
+            disk,
+            "test.data",
+            &opts,
+            &OpenMode::IntegrityOnly,
+            None,
+            1,
+            None,
+        )
+        .unwrap();
 
         let mut read_buffer = vec![0u8; block_size];
         for _ in 0..block_number {
@@ -15,7 +15,6 @@
 // specific language governing permissions and limitations
 // under the License..
 
-use crate::layers::bio::MemDisk;
 use crate::os::Arc;
 use crate::os::HashMap;
 use crate::pfs::sys::cache::LruCache;
@@ -32,11 +31,134 @@ use crate::pfs::sys::metadata::{
     FILENAME_MAX_LEN, FULLNAME_MAX_LEN, MD_USER_DATA_SIZE, SGX_FILE_ID, SGX_FILE_MAJOR_VERSION,
 };
 use crate::pfs::sys::node::{FileNode, FileNodeRef, NodeType, NODE_SIZE};
-use crate::{bail, ensure, eos, AeadKey, BlockSet};
+use crate::{bail, ensure, eos, AeadKey, AeadMac, BlockSet};
 use core::cell::RefCell;
 use log::info;
 
 pub const SE_PAGE_SIZE: usize = 0x1000;
+
+/// Self-describing on-disk format header ("docket") carried in the metadata
+/// node. It turns the single brittle `major_version` equality check into a
+/// real compatibility matrix: a fixed magic marks the header, `format_version`
+/// selects the field layout, and `writer_version` records the crate release
+/// that created or last upgraded the file, for diagnostics.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Docket {
+    pub format_version: u32,
+    pub writer_version: u32,
+}
+
+/// Fixed marker distinguishing a docketed file; mirrors the existing
+/// `SGX_FILE_ID` sentinel.
+pub const DOCKET_MAGIC: u64 = SGX_FILE_ID;
+/// The layout version this build reads/writes natively.
+pub const CURRENT_FORMAT_VERSION: u32 = SGX_FILE_MAJOR_VERSION as u32;
+/// The lowest `format_version` this build can still open (and upgrade).
+pub const MIN_SUPPORTED_FORMAT_VERSION: u32 = 1;
+/// This crate's own version word, stamped into `writer_version` on create and on format upgrade.
+pub const WRITER_VERSION: u32 = 0x0001_0000;
+
+impl Docket {
+    /// Decode the docket from an already-read metadata node. The magic and
+    /// version live in the metadata *plaintext* header, so this needs no key.
+    fn read(metadata: &MetadataInfo) -> FsResult<Docket> {
+        let plaintext = &metadata.node.metadata.plaintext;
+        ensure!(
+            plaintext.file_id == DOCKET_MAGIC,
+            FsError::SgxError(SgxStatus::NotSgxFile)
+        );
+        Ok(Docket {
+            format_version: plaintext.major_version as u32,
+            writer_version: plaintext.writer_version as u32,
+        })
+    }
+
+    #[inline]
+    fn is_current(&self) -> bool {
+        self.format_version == CURRENT_FORMAT_VERSION
+    }
+
+    #[inline]
+    fn is_upgradable(&self) -> bool {
+        (MIN_SUPPORTED_FORMAT_VERSION..CURRENT_FORMAT_VERSION).contains(&self.format_version)
+    }
+}
+/// How the backing [`BlockSet`] is partitioned between the file-data region
+/// and the crash-recovery journal. The journal is always carved from the tail
+/// of the disk; everything before it serves data.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum JournalReservation {
+    /// An explicit number of tail blocks reserved for the journal.
+    Blocks(usize),
+    /// A fraction of the whole disk expressed as `numerator / denominator`
+    /// (e.g. `Ratio(1, 8)` reserves an eighth).
+    Ratio(usize, usize),
+}
+
+/// On-disk layout parameters fixed at create time and re-validated on every
+/// reopen, mirroring how `encrypt_flags` is recorded in the metadata header
+/// and rejected on mismatch.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct LayoutConfig {
+    pub journal: JournalReservation,
+}
+
+/// Historical split: one eighth of the disk for the journal, seven eighths for
+/// data. Callers passing `None` get exactly this, so existing volumes reopen
+/// unchanged.
+pub const DEFAULT_JOURNAL_RATIO: (usize, usize) = (1, 8);
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self {
+            journal: JournalReservation::Ratio(DEFAULT_JOURNAL_RATIO.0, DEFAULT_JOURNAL_RATIO.1),
+        }
+    }
+}
+
+impl LayoutConfig {
+    /// Resolve the reservation to an absolute number of tail blocks and check
+    /// it against the disk size: at least one block must remain for each of the
+    /// data and journal regions.
+    fn journal_blocks(&self, nblocks: usize) -> FsResult<usize> {
+        let reserved = match self.journal {
+            JournalReservation::Blocks(n) => n,
+            JournalReservation::Ratio(num, den) => {
+                ensure!(den > 0 && num < den, eos!(EINVAL));
+                nblocks - nblocks * (den - num) / den
+            }
+        };
+        ensure!(reserved >= 1 && reserved < nblocks, eos!(EINVAL));
+        Ok(reserved)
+    }
+}
+
+/// A data node whose stored GMAC did not match the MAC recomputed from its
+/// on-disk ciphertext during a [`scrub`](FileInner::scrub) pass.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CorruptNode {
+    pub physical_number: u64,
+    pub expected_gmac: AeadMac,
+    pub actual_gmac: AeadMac,
+}
+
+/// Result of an offline per-node checksum pass: how many data nodes were
+/// checked and which ones failed verification. Unlike the crash-recovery
+/// journal (which only covers torn writes), a scrub detects silent corruption
+/// anywhere in the volume in one sweep.
+#[derive(Clone, Debug, Default)]
+pub struct ScrubReport {
+    pub checked: usize,
+    pub corrupt: Vec<CorruptNode>,
+}
+
+impl ScrubReport {
+    #[inline]
+    pub fn is_clean(&self) -> bool {
+        self.corrupt.is_empty()
+    }
+}
+
 macro_rules! is_page_aligned {
     ($num:expr) => {
         $num & (SE_PAGE_SIZE - 1) == 0
@@ -52,13 +174,18 @@ impl<D: BlockSet> FileInner<D> {
         opts: &OpenOptions,
         mode: &OpenMode,
         cache_size: Option<usize>,
+        workers: usize,
+        layout: Option<LayoutConfig>,
     ) -> FsResult<Self> {
         let cache_size = Self::check_cache_size(cache_size)?;
         let file_name = path;
         let key_gen = FsKeyGen::new(mode)?;
 
-        let mut host_file = BlockFile::create(Self::subdisk_for_data(&disk)?);
-        let mut journal = RecoveryJournal::create(Self::subdisk_for_journal(&disk)?);
+        let journal_blocks = layout.unwrap_or_default().journal_blocks(disk.nblocks())?;
+
+        let mut host_file = BlockFile::create(Self::subdisk_for_data(&disk, journal_blocks)?);
+        let mut journal =
+            RecoveryJournal::create(Self::subdisk_for_journal(&disk, journal_blocks)?);
 
         let mut offset = 0;
         let (metadata, root_mht, rollback_nodes) = {
@@ -89,6 +216,17 @@ impl<D: BlockSet> FileInner<D> {
             (metadata, root_mht, rollback_nodes)
         };
 
+        // The layout is fixed at create time; a reopen whose requested journal
+        // reservation disagrees with what was recorded is rejected the same way
+        // an `encrypt_flags` mismatch is.
+        ensure!(
+            metadata.node.metadata.plaintext.journal_blocks as usize == journal_blocks,
+            eos!(EINVAL)
+        );
+
+        // The root subtree is unmaterialized iff the file actually has one on
+        // disk; a fresh/empty file has nothing to lazily load.
+        let root_loaded = metadata.encrypted_plain.size <= MD_USER_DATA_SIZE;
         let mut protected_file = Self {
             host_file,
             metadata,
@@ -102,21 +240,58 @@ impl<D: BlockSet> FileInner<D> {
             last_error: FsError::SgxError(SgxStatus::Success),
             status: FileStatus::NotInitialized,
             journal,
+            workers: Self::resolve_workers(workers),
             cache: LruCache::new(cache_size),
+            root_loaded,
+            in_batch: false,
         };
+        // Rolling back dirty data nodes has to touch their parent MHTs, so make
+        // sure the deferred root is resident before replaying pre-images.
         if !rollback_nodes.is_empty() {
+            protected_file.ensure_root_loaded()?;
             protected_file.rollback_nodes(rollback_nodes)?;
         }
         protected_file.status = FileStatus::Ok;
+
+        // An older-but-supported file opened writable is transparently
+        // rewritten into the current layout, journaled for crash rollback.
+        let docket = Docket::read(&protected_file.metadata)?;
+        if docket.is_upgradable() && opts.write {
+            protected_file.upgrade_format(docket)?;
+        }
         Ok(protected_file)
     }
 
+    /// Rewrite the metadata and root MHT into the current on-disk layout and
+    /// bump the stored `format_version`. The rewrite rides the normal
+    /// journaled flush path, so a crash mid-upgrade rolls back cleanly on the
+    /// next open and leaves the original version intact.
+    fn upgrade_format(&mut self, from: Docket) -> FsResult<()> {
+        info!(
+            "upgrading pfs format from v{} to v{}",
+            from.format_version, CURRENT_FORMAT_VERSION
+        );
+        self.metadata.node.metadata.plaintext.major_version = SGX_FILE_MAJOR_VERSION;
+        self.metadata.node.metadata.plaintext.writer_version = WRITER_VERSION;
+        // Rewriting the root MHT requires it resident first.
+        self.ensure_root_loaded()?;
+        // Force a full rewrite of metadata + root MHT through `flush`, which
+        // stages pre-images in the recovery journal before flipping the
+        // metadata `update_flag`.
+        self.need_writing = true;
+        self.root_mht.borrow_mut().need_writing = true;
+        self.flush()?;
+        Ok(())
+    }
+
     pub fn create(
         path: &str,
         disk: D,
         opts: &OpenOptions,
         mode: &OpenMode,
         cache_size: Option<usize>,
+        workers: usize,
+        layout: Option<LayoutConfig>,
     ) -> FsResult<Self> {
         let cache_size = Self::check_cache_size(cache_size)?;
         let file_name = path;
@@ -124,13 +299,22 @@ impl<D: BlockSet> FileInner<D> {
 
         let key_gen = FsKeyGen::new(mode)?;
 
+        let journal_blocks = layout.unwrap_or_default().journal_blocks(disk.nblocks())?;
+
         //Self::check_file_exist(opts, mode, path)?;
         // 10MB
-        let host_file = BlockFile::create(Self::subdisk_for_data(&disk)?);
-        let journal = RecoveryJournal::create(Self::subdisk_for_journal(&disk)?);
+        let host_file = BlockFile::create(Self::subdisk_for_data(&disk, journal_blocks)?);
+        let journal =
+            RecoveryJournal::create(Self::subdisk_for_journal(&disk, journal_blocks)?);
         let need_writing = true;
         let (metadata, root_mht, rollback_nodes) = {
-            let metadata = Self::new_file(file_name, mode)?;
+            let mut metadata = Self::new_file(file_name, mode)?;
+            // Record the chosen layout so a later reopen uses the same split
+            // without the caller re-specifying it.
+            metadata.node.metadata.plaintext.journal_blocks = journal_blocks as u64;
+            // Stamp the writer's own version so a later open can report which
+            // build created the file, instead of always reporting itself.
+            metadata.node.metadata.plaintext.writer_version = WRITER_VERSION;
             (
                 metadata,
                 FileNode::new_root_ref(mode.into()),
@@ -151,7 +335,11 @@ impl<D: BlockSet> FileInner<D> {
             last_error: FsError::SgxError(SgxStatus::Success),
             status: FileStatus::NotInitialized,
             journal,
+            workers: Self::resolve_workers(workers),
             cache: LruCache::new(cache_size),
+            // A brand-new file has no root subtree on disk to load.
+            root_loaded: true,
+            in_batch: false,
         };
         if !rollback_nodes.is_empty() {
             protected_file.rollback_nodes(rollback_nodes)?;
@@ -160,6 +348,36 @@ impl<D: BlockSet> FileInner<D> {
         Ok(protected_file)
     }
 
+    /// Materialize the root MHT on first access.
+    ///
+    /// [`open_file_v1`](Self::open_file_v1) leaves the root as a cheap handle
+    /// (fixed on-disk id plus the key/GMAC carried in the metadata). This
+    /// performs the deferred `read_from_disk` + AES-GCM verification exactly
+    /// once — subsequent calls are free — and is the hook through which
+    /// `get_mht_node_by_logic_number` pulls the root into residency the same
+    /// way it does any other MHT node.
+    pub(crate) fn ensure_root_loaded(&mut self) -> FsResult {
+        if self.root_loaded {
+            return Ok(());
+        }
+        {
+            let mut root_mht = self.root_mht.borrow_mut();
+            root_mht.read_from_disk(&mut self.host_file)?;
+            root_mht.decrypt(
+                &self.metadata.encrypted_plain.mht_key,
+                &self.metadata.encrypted_plain.mht_gmac,
+            )?;
+            root_mht.new_node = false;
+        }
+        self.root_loaded = true;
+        Ok(())
+    }
+
+    /// Read the metadata header, decode its [`Docket`] and dispatch to the
+    /// reader that understands that layout. Unknown or newer
+    /// `format_version`s are rejected here; older-but-supported ones are read
+    /// by the matching `open_file_vN` and upgraded later by
+    /// [`upgrade_format`](Self::upgrade_format) when the file is writable.
     fn open_file(
         host_file: &mut dyn HostFs,
         file_name: &str,
@@ -169,14 +387,23 @@ impl<D: BlockSet> FileInner<D> {
         let mut metadata = MetadataInfo::default();
         metadata.read_from_disk(host_file)?;
 
-        ensure!(
-            metadata.node.metadata.plaintext.file_id == SGX_FILE_ID,
-            FsError::SgxError(SgxStatus::NotSgxFile)
-        );
-        ensure!(
-            metadata.node.metadata.plaintext.major_version == SGX_FILE_MAJOR_VERSION,
-            eos!(ENOTSUP)
-        );
+        let docket = Docket::read(&metadata)?;
+        // Newer-than-known layouts cannot be interpreted safely.
+        ensure!(docket.is_current() || docket.is_upgradable(), eos!(ENOTSUP));
+
+        // Only one on-disk layout exists in this build, so every supported
+        // version is read by `open_file_v1`; a future `open_file_v2` slots in
+        // here keyed on `docket.format_version` without disturbing callers.
+        Self::open_file_v1(metadata, host_file, file_name, key_gen, mode)
+    }
+
+    fn open_file_v1(
+        mut metadata: MetadataInfo,
+        host_file: &mut dyn HostFs,
+        file_name: &str,
+        key_gen: &dyn RestoreKey,
+        mode: &OpenMode,
+    ) -> FsResult<(MetadataInfo, FileNodeRef)> {
         ensure!(
             !metadata.update_flag(),
             FsError::SgxError(SgxStatus::RecoveryNeeded)
@@ -205,13 +432,16 @@ impl<D: BlockSet> FileInner<D> {
             FsError::SgxError(SgxStatus::NameMismatch)
         );
 
+        // Resolve only a cheap, integrity-anchored handle to the root MHT
+        // here: its fixed on-disk id plus the `mht_key`/`mht_gmac` already
+        // recorded in the (now decrypted) metadata. The root block I/O and its
+        // AES-GCM verification are deferred to the first
+        // `get_mht_node_by_logic_number`, so opening a large file is O(1) and
+        // callers that only stat or read a tiny prefix never pay for the MHT
+        // subtree. Residency is then governed by the same `LruCache` as data
+        // nodes via [`ensure_root_loaded`](Self::ensure_root_loaded).
         let mut root_mht = FileNode::new_root(encrypt_flags);
         if metadata.encrypted_plain.size > MD_USER_DATA_SIZE {
-            root_mht.read_from_disk(host_file)?;
-            root_mht.decrypt(
-                &metadata.encrypted_plain.mht_key,
-                &metadata.encrypted_plain.mht_gmac,
-            )?;
             root_mht.new_node = false;
         }
         Ok((metadata, FileNode::build_ref(root_mht)))
@@ -240,7 +470,7 @@ impl<D: BlockSet> FileInner<D> {
 
             data_node.borrow_mut().encrypt_flags = self.metadata.encrypt_flags();
 
-            let mht_logical_number = RecoveryHandler::calculate_mht_logical_number(physical_number);
+            let mht_logical_number = RecoveryHandler::mht_logical_from_physical(physical_number);
 
             let parent_mht = self.get_mht_node_by_logic_number(mht_logical_number)?;
             // udpated the parent of data node
@@ -334,13 +564,90 @@ impl<D: BlockSet> FileInner<D> {
             .ok_or_else(|| eos!(EINVAL))
     }
 
-    fn subdisk_for_data(disk: &D) -> FsResult<D> {
-        disk.subset(0..disk.nblocks() * 7 / 8)
+    #[inline]
+    fn resolve_workers(workers: usize) -> usize {
+        crate::pfs::sys::file::parallel::resolve_workers(workers)
+    }
+
+    /// Data region: every block before the journal reservation at the tail.
+    fn subdisk_for_data(disk: &D, journal_blocks: usize) -> FsResult<D> {
+        disk.subset(0..disk.nblocks() - journal_blocks)
             .map_err(|e| FsError::Errno(e))
     }
 
-    fn subdisk_for_journal(disk: &D) -> FsResult<D> {
-        disk.subset(disk.nblocks() * 7 / 8..disk.nblocks())
+    /// Journal region: the `journal_blocks` tail blocks reserved for recovery.
+    fn subdisk_for_journal(disk: &D, journal_blocks: usize) -> FsResult<D> {
+        disk.subset(disk.nblocks() - journal_blocks..disk.nblocks())
             .map_err(|e| FsError::Errno(e))
     }
+
+    /// Open the file read-only and run a full per-node checksum [`scrub`], then
+    /// drop the handle. This is the offline `fsck`-style entry point that sits
+    /// alongside [`open`](Self::open). The scrub itself never writes; the open
+    /// it goes through replays a pending recovery journal, as every open does.
+    pub fn verify(
+        path: &str,
+        disk: D,
+        mode: &OpenMode,
+        cache_size: Option<usize>,
+        layout: Option<LayoutConfig>,
+    ) -> FsResult<ScrubReport> {
+        let opts = OpenOptions::new().read(true);
+        let mut file = Self::open(path, disk, &opts, mode, cache_size, 1, layout)?;
+        file.scrub()
+    }
+
+    /// Walk every data node, recompute its GMAC from the on-disk ciphertext and
+    /// compare it against the MAC recorded in the parent MHT, collecting every
+    /// mismatch into a [`ScrubReport`] instead of bailing on the first error.
+    ///
+    /// The pass is deliberately separate from the normal read path: it resolves
+    /// each parent MHT via [`RecoveryHandler::mht_logical_from_physical`],
+    /// reads the node into a scratch [`FileNode`], and verifies it there. No
+    /// `need_writing` flag is ever set and nothing is committed, so `scrub` is
+    /// valid on a read-only handle.
+    pub fn scrub(&mut self) -> FsResult<ScrubReport> {
+        let mut report = ScrubReport::default();
+
+        let size = self.metadata.encrypted_plain.size;
+        if size <= MD_USER_DATA_SIZE {
+            return Ok(report);
+        }
+        // The root MHT anchors the whole tree; materialize it once (read-only)
+        // so parent lookups below can verify against it.
+        self.ensure_root_loaded()?;
+
+        let encrypt_flags = self.metadata.encrypt_flags();
+        let n_data = (size - MD_USER_DATA_SIZE).div_ceil(NODE_SIZE) as u64;
+        for logical in 0..n_data {
+            let offset = MD_USER_DATA_SIZE + (logical as usize) * NODE_SIZE;
+            let (_, physical) = RecoveryHandler::get_data_node_numbers(offset);
+            let mht_logical = RecoveryHandler::mht_logical_from_physical(physical);
+            let parent = self.get_mht_node_by_logic_number(mht_logical)?;
+
+            // Verify against a scratch node so neither the LRU nor any resident
+            // node's dirty state is disturbed.
+            let mut node = FileNode::new(NodeType::Data, logical, physical, encrypt_flags);
+            node.parent = Some(parent);
+            self.host_file
+                .read(physical, node.ciphertext.node_data.data.as_mut())?;
+
+            let gcm = node.get_gcm_data()?;
+            report.checked += 1;
+            if node.decrypt(&gcm.key, &gcm.mac).is_err() {
+                // Re-encrypting here would pick a fresh nonce and sign whatever
+                // scratch plaintext `decrypt` left behind on failure, which can
+                // never reproduce the tag actually on disk. Recompute the GMAC
+                // from the ciphertext we just read instead, so the report
+                // reflects what is really there rather than a fabricated value.
+                let actual = node.ciphertext_gmac(&gcm.key).unwrap_or_default();
+                report.corrupt.push(CorruptNode {
+                    physical_number: physical,
+                    expected_gmac: gcm.mac,
+                    actual_gmac: actual,
+                });
+            }
+        }
+        Ok(report)
+    }
 }
@@ -0,0 +1,116 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License..
+
+//! A minimal worker pool for parallel per-node AEAD.
+//!
+//! The file nodes themselves live behind `Arc<RefCell<_>>` and are neither
+//! `Send` nor `Sync`, so the parallel region operates only on *owned*
+//! ciphertext/plaintext buffers and the independently-derived per-node keys.
+//! Results are collected and spliced back into the cache serially, under the
+//! `FileInner` lock, preserving MAC-chain ordering. When the worker count is
+//! 1 the work runs inline, reproducing the single-threaded behaviour exactly.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::pfs::sys::node::NODE_SIZE;
+use crate::AeadKey;
+
+/// One unit of crypto work: a single 4KB node keyed independently of the rest
+/// of the batch, so jobs can run in any order across workers.
+pub struct CryptJob {
+    pub physical_number: u64,
+    pub key: AeadKey,
+    pub buf: [u8; NODE_SIZE],
+}
+
+/// Clamp a caller-supplied worker count to at least 1, logging once if the
+/// caller asked for real parallelism.
+///
+/// `parallel_crypt` is ready to dispatch AEAD across a pool, but nothing in
+/// the read/write path calls it yet, so today every request runs
+/// single-threaded no matter what `workers` is set to. Surfacing that here
+/// means a caller who asks for `workers > 1` gets a log line saying so,
+/// rather than having the count silently accepted and ignored.
+pub fn resolve_workers(workers: usize) -> usize {
+    let workers = workers.max(1);
+    if workers > 1 {
+        log::debug!(
+            "workers={workers} requested, but multi-node AEAD dispatch is not wired into the \
+             read/write path yet; running single-threaded"
+        );
+    }
+    workers
+}
+
+/// Run `f` over every job, spreading the work across `workers` threads and
+/// returning the outputs in input order. `workers <= 1` runs inline.
+pub fn parallel_crypt<T, F>(jobs: Vec<CryptJob>, workers: usize, f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(&CryptJob) -> T + Sync,
+{
+    if workers <= 1 || jobs.len() <= 1 {
+        return jobs.iter().map(|job| f(job)).collect();
+    }
+
+    let n = jobs.len();
+    let n_workers = workers.min(n);
+    // Pre-size the output so each worker writes a disjoint slot without locking.
+    let mut out: Vec<Option<T>> = Vec::with_capacity(n);
+    out.resize_with(n, || None);
+
+    let cursor = AtomicUsize::new(0);
+    let jobs = &jobs;
+    let out_slots = SendSlots(out.as_mut_ptr(), n);
+
+    thread::scope(|scope| {
+        for _ in 0..n_workers {
+            let cursor = &cursor;
+            let f = &f;
+            scope.spawn(move || {
+                // Claim indices until the batch is exhausted.
+                loop {
+                    let idx = cursor.fetch_add(1, Ordering::Relaxed);
+                    if idx >= n {
+                        break;
+                    }
+                    let value = f(&jobs[idx]);
+                    // Safety: every `idx` is claimed by exactly one worker via
+                    // the atomic cursor, so the writes never alias.
+                    unsafe { out_slots.write(idx, value) };
+                }
+            });
+        }
+    });
+
+    out.into_iter().map(|slot| slot.unwrap()).collect()
+}
+
+// A raw pointer wrapper letting scoped workers write disjoint output slots.
+// Exclusivity of indices is guaranteed by the atomic cursor in the loop above.
+struct SendSlots<T>(*mut Option<T>, usize);
+
+unsafe impl<T: Send> Send for SendSlots<T> {}
+unsafe impl<T: Send> Sync for SendSlots<T> {}
+
+impl<T> SendSlots<T> {
+    unsafe fn write(&self, idx: usize, value: T) {
+        debug_assert!(idx < self.1);
+        self.0.add(idx).write(Some(value));
+    }
+}
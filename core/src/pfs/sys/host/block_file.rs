@@ -76,6 +76,17 @@ impl<D: BlockSet> HostFs for BlockFile<D> {
     fn flush(&mut self) -> FsResult {
         self.flush()
     }
+
+    fn len(&self) -> FsResult<usize> {
+        Ok(self.size)
+    }
+
+    fn set_len(&mut self, len: usize) -> FsResult {
+        // The backing BlockSet is fixed-size; we only track the logical high
+        // watermark so `len()` reflects writes, as the node write path does.
+        self.size = len;
+        Ok(())
+    }
 }
 
 pub struct RecoveryFile<D> {
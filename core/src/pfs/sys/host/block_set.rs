@@ -0,0 +1,103 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License..
+
+use super::HostFs;
+use crate::os::{Arc, Mutex};
+use crate::{prelude::*, BlockId, BlockSet, BufMut, BufRef, Errno, Error};
+use core::ops::Range;
+
+/// Adapts a boxed [`HostFs`] backend into a [`BlockSet`], so a plain `HostFs`
+/// implementation (e.g. [`MmapHostFile`](super::mmap_file::MmapHostFile), or
+/// any caller-supplied backend) can be passed anywhere `ProtectedFile::open`/
+/// `create` expect a `D: BlockSet` disk — [`BlockFile`](super::block_file)
+/// plays the same adapter role in the other direction, wrapping a `BlockSet`
+/// to present it as a `HostFs`.
+///
+/// The backend is shared behind an `Arc<Mutex<_>>` so [`subset`](Self::subset)
+/// can hand back a second `HostFsBlockSet` windowed onto the same underlying
+/// store, the way `FileInner::open` splits one disk into a data region and a
+/// journal region.
+pub struct HostFsBlockSet {
+    backend: Arc<Mutex<Box<dyn HostFs>>>,
+    range: Range<usize>,
+}
+
+impl HostFsBlockSet {
+    /// Wrap `backend`, addressing its first `nblocks` `NODE_SIZE` blocks.
+    pub fn new(backend: Box<dyn HostFs>, nblocks: usize) -> Self {
+        Self {
+            backend: Arc::new(Mutex::new(backend)),
+            range: 0..nblocks,
+        }
+    }
+
+    /// Translate a block number relative to this view into one relative to
+    /// the whole shared backend, rejecting anything outside `self.range`.
+    fn absolute(&self, pos: BlockId) -> Result<u64> {
+        let number = pos
+            .checked_add(self.range.start)
+            .filter(|n| *n < self.range.end)
+            .ok_or_else(|| Error::with_msg(Errno::InvalidArgs, "block out of range"))?;
+        Ok(number as u64)
+    }
+}
+
+impl Clone for HostFsBlockSet {
+    fn clone(&self) -> Self {
+        Self {
+            backend: self.backend.clone(),
+            range: self.range.clone(),
+        }
+    }
+}
+
+impl BlockSet for HostFsBlockSet {
+    fn read(&self, pos: BlockId, mut buf: BufMut) -> Result<()> {
+        let number = self.absolute(pos)?;
+        self.backend
+            .lock()
+            .read(number, buf.as_mut_slice())
+            .map_err(|e| e.to_errno())
+    }
+
+    fn write(&self, pos: BlockId, buf: BufRef) -> Result<()> {
+        let number = self.absolute(pos)?;
+        self.backend
+            .lock()
+            .write(number, buf.as_slice())
+            .map_err(|e| e.to_errno())
+    }
+
+    fn subset(&self, range: Range<BlockId>) -> Result<Self> {
+        ensure!(
+            range.start <= range.end && range.end <= self.range.end - self.range.start,
+            Error::with_msg(Errno::InvalidArgs, "subset range out of bounds")
+        );
+        Ok(Self {
+            backend: self.backend.clone(),
+            range: (self.range.start + range.start)..(self.range.start + range.end),
+        })
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.backend.lock().flush().map_err(|e| e.to_errno())
+    }
+
+    fn nblocks(&self) -> usize {
+        self.range.end - self.range.start
+    }
+}
@@ -69,16 +69,79 @@ impl<D: BlockSet> RawJournal<D> {
         ensure!(size >= INNER_OFFSET, FsError::Errno(Errno::InvalidArgs));
         Ok(size - INNER_OFFSET)
     }
+
+    // Discard the journal contents after a clean commit. The records are no
+    // longer needed for rollback, so the meta length is rewound to the inner
+    // offset and the in-memory buffer is dropped; the next append starts a
+    // fresh, empty log.
+    pub fn reset(&mut self) -> FsResult {
+        self.buf.clear();
+        self.flush_pos = INNER_OFFSET;
+        self.disk.write_slice(0, &self.flush_pos.to_le_bytes())?;
+        self.disk.flush()?;
+        Ok(())
+    }
+}
+
+// Per-entry trailer: a CRC32 over the entry payload lets replay tell a good
+// record from a torn tail write. A Node payload is the physical block number
+// plus the node bytes; a Commit payload is the group's node count plus a
+// rolling CRC over every node in the group.
+const CRC_SIZE: usize = 4;
+const COMMIT_PAYLOAD_SIZE: usize = 4 + 4; // count(u32) + rolling crc(u32)
+
+/// On-disk size of a Node entry: flag + payload + per-entry CRC.
+const NODE_ENTRY_SIZE: usize = 1 + RECOVERY_NODE_SIZE + CRC_SIZE;
+/// On-disk size of a Commit entry: flag + {count, rolling crc}.
+const COMMIT_ENTRY_SIZE: usize = 1 + COMMIT_PAYLOAD_SIZE;
+
+/// Streaming CRC32 (IEEE reflected polynomial), used both for per-entry
+/// integrity and for the rolling checksum that seals a commit group. No
+/// lookup table so it stays usable in the enclave `no_std` build.
+#[derive(Clone)]
+struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    fn new() -> Self {
+        Self { state: 0xFFFF_FFFF }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.state ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (self.state & 1).wrapping_neg();
+                self.state = (self.state >> 1) ^ (0xEDB8_8320 & mask);
+            }
+        }
+    }
+
+    fn finalize(&self) -> u32 {
+        !self.state
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finalize()
 }
 
 pub struct RecoveryJournal<D> {
     raw: RawJournal<D>,
+    // Accumulated state for the open commit group.
+    group_count: u32,
+    group_crc: Crc32,
 }
 
 impl<D: BlockSet> RecoveryJournal<D> {
     pub fn create(disk: D) -> FsResult<RecoveryJournal<D>> {
         Ok(Self {
             raw: RawJournal::create(disk)?,
+            group_count: 0,
+            group_crc: Crc32::new(),
         })
     }
 
@@ -89,18 +152,37 @@ impl<D: BlockSet> RecoveryJournal<D> {
         );
         let flag = JournalFlag::Node;
         self.raw.append(&[flag as u8])?;
-        self.raw.append(data)
+        self.raw.append(data)?;
+        self.raw.append(&crc32(data).to_le_bytes())?;
+        // Fold the node into the open group.
+        self.group_count += 1;
+        self.group_crc.update(data);
+        Ok(())
     }
 
+    /// Terminate the current commit group, recording its node count and the
+    /// rolling CRC over every node. Replay applies the group only if this
+    /// record is present and both match.
     pub fn commit(&mut self) -> FsResult {
         let flag = JournalFlag::Commit;
         self.raw.append(&[flag as u8])?;
+        self.raw.append(&self.group_count.to_le_bytes())?;
+        self.raw.append(&self.group_crc.finalize().to_le_bytes())?;
+        self.group_count = 0;
+        self.group_crc = Crc32::new();
         Ok(())
     }
 
     pub fn flush(&mut self) -> FsResult {
         self.raw.flush()
     }
+
+    pub fn reset(&mut self) -> FsResult {
+        self.group_count = 0;
+        self.group_crc = Crc32::new();
+        self.raw.reset()
+    }
+
     pub fn size(&self) -> FsResult<usize> {
         self.raw.size()
     }
@@ -116,79 +198,95 @@ pub fn recovery<D: BlockSet>(
 ) -> FsResult<HashMap<u64, Arc<RefCell<FileNode>>>> {
     let log_size = recovery.size()?;
     let mut offset = 0;
-    let mut last_commit_offset = offset;
 
     let mut flag_buf = vec![0u8; 1];
-
-    while offset < log_size {
-        recovery.read(offset, flag_buf.as_mut_slice())?;
-        let flag: JournalFlag = flag_buf[0].into();
-        offset += 1;
-
-        match flag {
-            JournalFlag::Node => {
-                // just find the last commit offset, skip the node
-                offset += RECOVERY_NODE_SIZE;
-            }
-            JournalFlag::Commit => {
-                last_commit_offset = offset;
-            }
-        }
-    }
-
-    offset = 0;
-    let mut recovery_handler = RecoveryHandler::new(HashMap::new());
     let mut data_buf = [0_u8; RECOVERY_NODE_SIZE];
-
-    let mut rollback_nodes = HashMap::new();
-
-    while offset < log_size {
-        let mut left_size = log_size - offset;
+    let mut crc_buf = [0_u8; CRC_SIZE];
+    let mut commit_buf = [0_u8; COMMIT_PAYLOAD_SIZE];
+
+    // Forward scan: buffer the nodes of the open group and only accept them
+    // once a well-formed, CRC-matching Commit closes the group. A torn entry
+    // or a trailing partial group (crash mid-commit) ends the scan and is
+    // discarded rather than applied.
+    let mut committed: Vec<(u64, [u8; NODE_SIZE])> = Vec::new();
+    let mut group: Vec<(u64, [u8; NODE_SIZE])> = Vec::new();
+    let mut group_crc = Crc32::new();
+
+    'scan: while offset < log_size {
+        let left_size = log_size - offset;
         if left_size < 1 {
             break;
         }
         recovery.read(offset, flag_buf.as_mut_slice())?;
-        let flag: JournalFlag = flag_buf[0].into();
         offset += 1;
-        left_size -= 1;
 
-        match flag {
-            JournalFlag::Node => {
-                if left_size < RECOVERY_NODE_SIZE {
+        match flag_buf[0] {
+            flag if flag == JournalFlag::Node as u8 => {
+                if left_size < NODE_ENTRY_SIZE {
                     break;
                 }
                 recovery.read(offset, data_buf.as_mut_slice())?;
+                offset += RECOVERY_NODE_SIZE;
+                recovery.read(offset, crc_buf.as_mut_slice())?;
+                offset += CRC_SIZE;
+
+                // Reject a torn node: its payload CRC must match.
+                if crc32(&data_buf) != u32::from_le_bytes(crc_buf) {
+                    break 'scan;
+                }
 
                 let mut number = [0u8; 8];
                 number.copy_from_slice(&data_buf[0..8]);
                 let physical_node_number = u64::from_ne_bytes(number);
 
-                if RecoveryHandler::is_mht_node(physical_node_number) {
-                    recovery_handler
-                        .push_raw_mht(physical_node_number, data_buf[8..].try_into().unwrap());
+                group_crc.update(&data_buf);
+                group.push((physical_node_number, data_buf[8..].try_into().unwrap()));
+            }
+            flag if flag == JournalFlag::Commit as u8 => {
+                if left_size < COMMIT_ENTRY_SIZE {
+                    break;
                 }
-                offset += RECOVERY_NODE_SIZE;
-                if offset >= last_commit_offset {
-                    // record the first version of data node
-                    if !rollback_nodes.contains_key(&physical_node_number)
-                        && !RecoveryHandler::is_mht_node(physical_node_number)
-                    {
-                        debug!("insert committed node: {}", physical_node_number);
-                        let encrypted_data = EncryptedData {
-                            data: data_buf[8..].try_into().unwrap(),
-                        };
-                        let data_node =
-                            recovery_handler.decrypt_node(physical_node_number, encrypted_data);
-                        rollback_nodes.insert(physical_node_number, data_node);
-                    }
+                recovery.read(offset, commit_buf.as_mut_slice())?;
+                offset += COMMIT_PAYLOAD_SIZE;
+
+                let count = u32::from_le_bytes(commit_buf[0..4].try_into().unwrap());
+                let rolling = u32::from_le_bytes(commit_buf[4..8].try_into().unwrap());
+
+                // Apply the group only if the terminating commit is intact.
+                if count as usize == group.len() && rolling == group_crc.finalize() {
+                    committed.append(&mut group);
+                } else {
+                    break 'scan;
                 }
-                source.write(physical_node_number, &data_buf[8..])?;
-            }
-            JournalFlag::Commit => {
-                // do nothing
+                group.clear();
+                group_crc = Crc32::new();
             }
+            // An unrecognized flag marks the torn tail; stop replaying.
+            _ => break 'scan,
+        }
+    }
+
+    let mut recovery_handler = RecoveryHandler::new(HashMap::new());
+    let mut rollback_nodes = HashMap::new();
+
+    for (physical_node_number, node) in &committed {
+        if RecoveryHandler::is_mht_node(*physical_node_number) {
+            recovery_handler.push_raw_mht(*physical_node_number, *node);
         }
     }
+
+    for (physical_node_number, node) in &committed {
+        if !rollback_nodes.contains_key(physical_node_number)
+            && !RecoveryHandler::is_mht_node(*physical_node_number)
+        {
+            debug!("insert committed node: {}", physical_node_number);
+            let encrypted_data = EncryptedData { data: *node };
+            let data_node = recovery_handler.decrypt_node(*physical_node_number, encrypted_data);
+            rollback_nodes.insert(*physical_node_number, data_node);
+        }
+        source.write(*physical_node_number, node)?;
+    }
+
     source.flush()?;
     Ok(rollback_nodes)
 }
@@ -249,8 +347,8 @@ mod tests {
         journal.flush().unwrap();
 
         let size = journal.raw.size().unwrap();
-        // data blocks + journal flag(1B) * 2
-        let expected_size = RECOVERY_NODE_SIZE + 2;
+        // node entry (flag + node + crc) + commit entry (flag + count + crc)
+        let expected_size = (1 + RECOVERY_NODE_SIZE + 4) + (1 + 8);
         assert_eq!(size, expected_size);
     }
 }
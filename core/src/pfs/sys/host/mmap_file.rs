@@ -0,0 +1,227 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License..
+
+use super::HostFs;
+use crate::pfs::sys::error::{FsError, FsResult};
+use crate::pfs::sys::node::NODE_SIZE;
+use crate::{ensure, eos};
+use std::ffi::CString;
+use std::fs::OpenOptions;
+use std::io::Error;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::ptr;
+
+fn errno() -> i32 {
+    Error::last_os_error().raw_os_error().unwrap_or(libc::EIO)
+}
+
+/// Well-known `f_type` magic numbers (see `statfs(2)`) for filesystems whose
+/// client-side page cache is not coherent with a concurrent writer the way a
+/// local filesystem's is. `mmap`ing a node store on one of these risks a
+/// reader observing a torn node (or, on a revalidating NFS client, a `SIGBUS`
+/// from a page evicted out from under the mapping) instead of the clean
+/// read-after-write a [`HostFile`](super::HostFile) gets from `pread`/`pwrite`.
+const NFS_SUPER_MAGIC: i64 = 0x6969;
+const SMB_SUPER_MAGIC: i64 = 0x517b;
+const CIFS_MAGIC_NUMBER: i64 = 0xff53_4d42_u32 as i64;
+
+/// True if `path` resides on a network filesystem where `mmap` is unsafe to
+/// use as a node store.
+///
+/// Errors (e.g. the path not existing yet) are treated as "don't know" and
+/// resolved to `false` so callers fall back to the conservative default of
+/// trusting the caller's `prefer_mmap` choice; the probe exists to veto an
+/// explicit opt-in, not to gate it.
+pub fn is_network_fs(path: &Path) -> bool {
+    let Some(path_str) = path.to_str() else {
+        return false;
+    };
+    let Ok(cpath) = CString::new(path_str) else {
+        return false;
+    };
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::statfs(cpath.as_ptr(), &mut stat) };
+    if ret != 0 {
+        return false;
+    }
+    let f_type = stat.f_type as i64;
+    matches!(
+        f_type,
+        NFS_SUPER_MAGIC | SMB_SUPER_MAGIC | CIFS_MAGIC_NUMBER
+    )
+}
+
+/// A [`HostFs`] backend that serves nodes out of a shared `mmap` of the whole
+/// file instead of issuing a syscall per node. A drop-in alternative to
+/// [`HostFile`](super::HostFile) wherever a `Box<dyn HostFs>` is accepted.
+///
+/// Only safe on a filesystem whose page cache is coherent with the writer's
+/// writes, which is why callers are expected to route through
+/// [`is_network_fs`] first rather than constructing this unconditionally —
+/// see [`open_host_fs`], which does exactly that.
+#[derive(Debug)]
+pub struct MmapHostFile {
+    fd: RawFd,
+    file: std::fs::File,
+    map: *mut libc::c_void,
+    // Length of the current mapping in bytes: the file's length after `open`
+    // or `set_len`, and a whole number of nodes after `ensure_mapped` grows it.
+    mapped_len: usize,
+    readonly: bool,
+}
+
+// The raw pointer only ever addresses a `mmap`ed region owned exclusively by
+// this struct; it is not aliased outside of it.
+unsafe impl Send for MmapHostFile {}
+
+impl MmapHostFile {
+    pub fn open(name: &Path, readonly: bool) -> FsResult<MmapHostFile> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(!readonly)
+            .create(!readonly)
+            .custom_flags(libc::O_LARGEFILE)
+            .open(name)
+            .map_err(|e| FsError::OsError(e.raw_os_error().unwrap_or(libc::EIO)))?;
+        let fd = file.as_raw_fd();
+
+        let mapped_len = file
+            .metadata()
+            .map_err(|e| FsError::OsError(e.raw_os_error().unwrap_or(libc::EIO)))?
+            .len() as usize;
+
+        let mut host_file = MmapHostFile {
+            fd,
+            file,
+            map: ptr::null_mut(),
+            mapped_len: 0,
+            readonly,
+        };
+        if mapped_len > 0 {
+            host_file.remap(mapped_len)?;
+        }
+        Ok(host_file)
+    }
+
+    fn unmap(&mut self) {
+        if !self.map.is_null() {
+            unsafe { libc::munmap(self.map, self.mapped_len) };
+            self.map = ptr::null_mut();
+            self.mapped_len = 0;
+        }
+    }
+
+    fn remap(&mut self, new_len: usize) -> FsResult {
+        self.unmap();
+        let prot = if self.readonly {
+            libc::PROT_READ
+        } else {
+            libc::PROT_READ | libc::PROT_WRITE
+        };
+        let map =
+            unsafe { libc::mmap(ptr::null_mut(), new_len, prot, libc::MAP_SHARED, self.fd, 0) };
+        ensure!(map != libc::MAP_FAILED, FsError::OsError(errno()));
+        self.map = map;
+        self.mapped_len = new_len;
+        Ok(())
+    }
+
+    /// Grow the backing file and mapping so that `number`'s node is covered.
+    fn ensure_mapped(&mut self, number: u64) -> FsResult {
+        let needed = (number as usize + 1) * NODE_SIZE;
+        if needed <= self.mapped_len {
+            return Ok(());
+        }
+        ensure!(!self.readonly, eos!(libc::EROFS));
+        self.file
+            .set_len(needed as u64)
+            .map_err(|e| FsError::OsError(errno_of(&e)))?;
+        self.remap(needed)
+    }
+}
+
+fn errno_of(e: &std::io::Error) -> i32 {
+    e.raw_os_error().unwrap_or(libc::EIO)
+}
+
+impl HostFs for MmapHostFile {
+    fn read(&mut self, number: u64, node: &mut dyn AsMut<[u8]>) -> FsResult {
+        let node = node.as_mut();
+        ensure!(node.len() == NODE_SIZE, eos!(libc::EINVAL));
+        let offset = number as usize * NODE_SIZE;
+        ensure!(offset + NODE_SIZE <= self.mapped_len, eos!(libc::EINVAL));
+        unsafe {
+            ptr::copy_nonoverlapping(
+                (self.map as *const u8).add(offset),
+                node.as_mut_ptr(),
+                NODE_SIZE,
+            );
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, number: u64, node: &dyn AsRef<[u8]>) -> FsResult {
+        let node = node.as_ref();
+        ensure!(node.len() == NODE_SIZE, eos!(libc::EINVAL));
+        self.ensure_mapped(number)?;
+        let offset = number as usize * NODE_SIZE;
+        unsafe {
+            ptr::copy_nonoverlapping(node.as_ptr(), (self.map as *mut u8).add(offset), NODE_SIZE);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> FsResult {
+        if self.map.is_null() {
+            return Ok(());
+        }
+        let ret = unsafe { libc::msync(self.map, self.mapped_len, libc::MS_SYNC) };
+        ensure!(ret == 0, FsError::OsError(errno()));
+        Ok(())
+    }
+
+    fn len(&self) -> FsResult<usize> {
+        Ok(self.mapped_len)
+    }
+
+    fn set_len(&mut self, len: usize) -> FsResult {
+        ensure!(!self.readonly, eos!(libc::EROFS));
+        self.file
+            .set_len(len as u64)
+            .map_err(|e| FsError::OsError(errno_of(&e)))?;
+        self.remap(len)
+    }
+}
+
+impl Drop for MmapHostFile {
+    fn drop(&mut self) {
+        let _ = self.flush();
+        self.unmap();
+    }
+}
+
+/// Open `name` as a [`HostFs`] backend, honoring `prefer_mmap` unless
+/// [`is_network_fs`] vetoes it: a caller asking for `mmap` on a network
+/// filesystem silently gets the safe [`HostFile`](super::HostFile) instead.
+pub fn open_host_fs(name: &Path, readonly: bool, prefer_mmap: bool) -> FsResult<Box<dyn HostFs>> {
+    if prefer_mmap && !is_network_fs(name) {
+        return Ok(Box::new(MmapHostFile::open(name, readonly)?));
+    }
+    Ok(Box::new(super::HostFile::open(name, readonly)?))
+}
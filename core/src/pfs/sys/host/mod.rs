@@ -5,7 +5,7 @@ use super::node::{
 };
 use crate::pfs::sys::error::ENOTSUP;
 use crate::{bail, eos};
-use crate::{ensure, AeadKey};
+use crate::{ensure, AeadKey, AeadMac};
 use core::cell::RefCell;
 use hashbrown::HashMap;
 use libc::c_void;
@@ -19,8 +19,12 @@ use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::path::Path;
 use std::sync::Arc;
 
+pub mod block_set;
+pub mod compressed;
 pub mod journal;
+pub mod mmap_file;
 pub mod raw_file;
+pub mod split;
 
 const MILISECONDS_SLEEP_FOPEN: u32 = 10;
 const MAX_FOPEN_RETRIES: usize = 10;
@@ -50,10 +54,35 @@ impl From<u8> for JournalFlag {
     }
 }
 
+/// The pluggable backing store behind the protected-FS stack.
+///
+/// Addressing is by `NODE_SIZE` node number (not byte offset) to match the
+/// Merkle-tree physical numbering the rest of the crate relies on. The trait
+/// is object-safe, so a `Box<dyn HostFs>` can back a [`HostFile`], a
+/// [`BlockFile`](block_file::BlockFile) over an in-memory [`MemDisk`], a
+/// remote/block-device target, or any custom scheme — the metadata, MHT and
+/// LRU-cache logic above is entirely backend-agnostic.
+///
+/// `FileInner`/`ProtectedFile` are generic over [`BlockSet`](crate::BlockSet),
+/// not `HostFs`, directly; [`block_set::HostFsBlockSet`] adapts a boxed
+/// `HostFs` the other way so a backend written against this trait can still
+/// be handed to `ProtectedFile::open`/`create` as the disk.
 pub trait HostFs {
     fn read(&mut self, number: u64, node: &mut dyn AsMut<[u8]>) -> FsResult;
     fn write(&mut self, number: u64, node: &dyn AsRef<[u8]>) -> FsResult;
     fn flush(&mut self) -> FsResult;
+
+    /// Current backing size in bytes. Defaults to unsupported for backends
+    /// (such as the append-only recovery file) that cannot report it.
+    fn len(&self) -> FsResult<usize> {
+        bail!(eos!(ENOTSUP))
+    }
+
+    /// Grow or shrink the backing store to `len` bytes. Defaults to
+    /// unsupported for fixed-size or append-only backends.
+    fn set_len(&mut self, _len: usize) -> FsResult {
+        bail!(eos!(ENOTSUP))
+    }
 }
 
 #[derive(Debug)]
@@ -88,18 +117,61 @@ impl HostFs for HostFile {
     fn flush(&mut self) -> FsResult {
         self.raw.flush().map_err(|err| FsError::OsError(err))
     }
+
+    fn len(&self) -> FsResult<usize> {
+        self.raw.size().map_err(FsError::OsError)
+    }
+}
+
+/// Supplies the keys recovery needs to decrypt a file's metadata and MHT
+/// chain. A [`RecoveryHandler`] built with `with_key_provider` asks this
+/// provider for its keys. Every lookup the handler makes passes
+/// `EncryptFlags::UserKey`, and journal replay (`journal::recovery`) builds
+/// its handler with [`DefaultKeyProvider`].
+pub trait KeyProvider {
+    /// The key that unwraps the encrypted metadata node, chosen from the
+    /// file's `EncryptFlags`/key policy.
+    fn metadata_key(&self, encrypt_flags: EncryptFlags) -> FsResult<AeadKey>;
+
+    /// The per-file MHT key. Defaults to the metadata key, since the MHT key
+    /// is normally carried inside the decrypted metadata node.
+    fn mht_key(&self, encrypt_flags: EncryptFlags) -> FsResult<AeadKey> {
+        self.metadata_key(encrypt_flags)
+    }
+}
+
+/// The historical behavior: always hand back `AeadKey::default()`. Used for
+/// files created with the default/user key and as a test default.
+#[derive(Debug, Default)]
+pub struct DefaultKeyProvider;
+
+impl KeyProvider for DefaultKeyProvider {
+    fn metadata_key(&self, _encrypt_flags: EncryptFlags) -> FsResult<AeadKey> {
+        Ok(AeadKey::default())
+    }
 }
 
 pub struct RecoveryHandler {
     raw_mhts: HashMap<u64, EncryptedData>,
     mhts: HashMap<u64, Arc<RefCell<FileNode>>>,
+    key_provider: Arc<dyn KeyProvider>,
 }
 
 impl RecoveryHandler {
     pub fn new(raw_mhts: HashMap<u64, EncryptedData>) -> Self {
+        Self::with_key_provider(raw_mhts, Arc::new(DefaultKeyProvider))
+    }
+
+    /// Construct a handler that derives its keys from `key_provider` instead of
+    /// unconditionally using the default key.
+    pub fn with_key_provider(
+        raw_mhts: HashMap<u64, EncryptedData>,
+        key_provider: Arc<dyn KeyProvider>,
+    ) -> Self {
         Self {
             raw_mhts,
             mhts: HashMap::new(),
+            key_provider,
         }
     }
 
@@ -148,11 +220,22 @@ impl RecoveryHandler {
         logical_number: u64,
         encrypt_flags: EncryptFlags,
     ) -> Arc<RefCell<FileNode>> {
+        self.try_get_mht_node(logical_number, encrypt_flags).unwrap()
+    }
+
+    /// Fallible form of [`get_mht_node`](Self::get_mht_node): propagates a
+    /// GMAC-verification failure instead of panicking, so the fsck entry
+    /// points can record corruption rather than abort.
+    fn try_get_mht_node(
+        &mut self,
+        logical_number: u64,
+        encrypt_flags: EncryptFlags,
+    ) -> FsResult<Arc<RefCell<FileNode>>> {
         if logical_number == 0 {
             let physical_number = 1;
 
             if let Some(mht_node) = self.mhts.get(&physical_number) {
-                return mht_node.clone();
+                return Ok(mht_node.clone());
             }
 
             let mut root_mht = FileNode::new(
@@ -161,41 +244,44 @@ impl RecoveryHandler {
                 physical_number,
                 encrypt_flags,
             );
-            root_mht.ciphertext.node_data = self.raw_mhts.get(&physical_number).unwrap().clone();
+            root_mht.ciphertext.node_data = self
+                .raw_mhts
+                .get(&physical_number)
+                .ok_or_else(|| eos!(crate::pfs::sys::error::EINVAL))?
+                .clone();
 
             let mut meta_info = MetadataInfo::default();
 
-            meta_info
-                .node
-                .metadata
-                .as_mut()
-                .copy_from_slice(self.raw_mhts.get(&0).unwrap().data.as_slice());
+            meta_info.node.metadata.as_mut().copy_from_slice(
+                self.raw_mhts
+                    .get(&0)
+                    .ok_or_else(|| eos!(crate::pfs::sys::error::EINVAL))?
+                    .data
+                    .as_slice(),
+            );
 
-            // TODO: get key from KeyGen
-            let key = AeadKey::default();
+            let key = self.key_provider.metadata_key(encrypt_flags)?;
 
-            meta_info.decrypt(&key).unwrap();
+            meta_info.decrypt(&key)?;
 
-            root_mht
-                .decrypt(
-                    &meta_info.encrypted_plain.mht_key,
-                    &meta_info.encrypted_plain.mht_gmac,
-                )
-                .unwrap();
+            root_mht.decrypt(
+                &meta_info.encrypted_plain.mht_key,
+                &meta_info.encrypted_plain.mht_gmac,
+            )?;
 
             let root_mht = FileNode::build_ref(root_mht);
             self.mhts.insert(physical_number, root_mht.clone());
-            return root_mht;
+            return Ok(root_mht);
         }
 
         let physical_number = 1 + logical_number * (ATTACHED_DATA_NODES_COUNT + 1);
 
         if let Some(mht_node) = self.mhts.get(&physical_number) {
-            return mht_node.clone();
+            return Ok(mht_node.clone());
         }
 
         let parent_mht_node =
-            self.get_mht_node((logical_number - 1) / CHILD_MHT_NODES_COUNT, encrypt_flags);
+            self.try_get_mht_node((logical_number - 1) / CHILD_MHT_NODES_COUNT, encrypt_flags)?;
 
         let mut mht_node = FileNode::new(
             NodeType::Mht,
@@ -204,15 +290,19 @@ impl RecoveryHandler {
             encrypt_flags,
         );
         mht_node.parent = Some(parent_mht_node);
-        mht_node.ciphertext.node_data = self.raw_mhts.get(&physical_number).unwrap().clone();
+        mht_node.ciphertext.node_data = self
+            .raw_mhts
+            .get(&physical_number)
+            .ok_or_else(|| eos!(crate::pfs::sys::error::EINVAL))?
+            .clone();
 
-        let gcm_data = mht_node.get_gcm_data().unwrap();
+        let gcm_data = mht_node.get_gcm_data()?;
 
-        mht_node.decrypt(&gcm_data.key, &gcm_data.mac).unwrap();
+        mht_node.decrypt(&gcm_data.key, &gcm_data.mac)?;
 
         let mht_node = FileNode::build_ref(mht_node);
-
-        mht_node
+        self.mhts.insert(physical_number, mht_node.clone());
+        Ok(mht_node)
     }
 
     fn decrypt_node(
@@ -220,12 +310,23 @@ impl RecoveryHandler {
         disk_physical_number: u64,
         node: EncryptedData,
     ) -> Arc<RefCell<FileNode>> {
+        self.try_decrypt_node(disk_physical_number, node).unwrap()
+    }
+
+    fn try_decrypt_node(
+        &mut self,
+        disk_physical_number: u64,
+        node: EncryptedData,
+    ) -> FsResult<Arc<RefCell<FileNode>>> {
         let source_offset = disk_physical_number * NODE_SIZE as u64;
         let (logical_number, physical_number) = Self::get_data_node_numbers(source_offset as usize);
-        assert!(physical_number == disk_physical_number);
+        ensure!(
+            physical_number == disk_physical_number,
+            eos!(crate::pfs::sys::error::EINVAL)
+        );
 
         let encrypt_flags = EncryptFlags::UserKey;
-        let mht_node = self.get_mht_node(logical_number, encrypt_flags);
+        let mht_node = self.try_get_mht_node(logical_number, encrypt_flags)?;
 
         let mut data_node = FileNode::new(
             NodeType::Data,
@@ -237,11 +338,328 @@ impl RecoveryHandler {
         data_node.parent = Some(mht_node);
         data_node.ciphertext.node_data = node;
 
-        let gcm_data = data_node.get_gcm_data().unwrap();
-        data_node.decrypt(&gcm_data.key, &gcm_data.mac).unwrap();
+        let gcm_data = data_node.get_gcm_data()?;
+        data_node.decrypt(&gcm_data.key, &gcm_data.mac)?;
+
+        Ok(FileNode::build_ref(data_node))
+    }
+}
+
+/// The type of an on-disk node, as reported by the fsck tooling.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NodeKind {
+    Meta,
+    Mht,
+    Data,
+}
+
+/// A node whose GMAC failed to verify during [`RecoveryHandler::check`].
+#[derive(Clone, Debug)]
+pub struct CorruptNode {
+    pub physical_number: u64,
+    pub logical_number: u64,
+    pub kind: NodeKind,
+    /// The GMAC the node's parent (or, for the root MHT, the metadata node)
+    /// records as correct.
+    pub expected_gmac: AeadMac,
+    /// The GMAC actually recomputed from the on-disk ciphertext.
+    pub actual_gmac: AeadMac,
+}
+
+/// The result of an offline volume check.
+#[derive(Clone, Debug, Default)]
+pub struct FsckReport {
+    pub checked: usize,
+    pub corrupt: Vec<CorruptNode>,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.corrupt.is_empty()
+    }
+}
+
+/// One row of a [`RecoveryHandler::dump`]: the logical→physical mapping and
+/// GMAC of a single node.
+#[derive(Clone, Debug)]
+pub struct DumpEntry {
+    pub physical_number: u64,
+    pub logical_number: u64,
+    pub kind: NodeKind,
+    pub mac: AeadMac,
+}
+
+/// The machine-readable node table produced by [`RecoveryHandler::dump`].
+#[derive(Clone, Debug, Default)]
+pub struct DumpTable {
+    pub entries: Vec<DumpEntry>,
+}
+
+impl DumpTable {
+    /// Serialize to fixed-width little-endian records:
+    /// `physical(u64) | logical(u64) | kind(u8) | mac(16)`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.entries.len() * (8 + 8 + 1 + 16));
+        for e in &self.entries {
+            out.extend_from_slice(&e.physical_number.to_le_bytes());
+            out.extend_from_slice(&e.logical_number.to_le_bytes());
+            out.push(match e.kind {
+                NodeKind::Meta => 0,
+                NodeKind::Mht => 1,
+                NodeKind::Data => 2,
+            });
+            out.extend_from_slice(e.mac.as_ref());
+        }
+        out
+    }
+}
+
+/// The protected-FS analogue of `thin_check` / `thin_repair` / `thin_dump`,
+/// built on the [`RecoveryHandler`] decrypt path so corruption surfaces as a
+/// structured report instead of a panic.
+impl RecoveryHandler {
+    pub(crate) fn mht_logical_from_physical(physical_number: u64) -> u64 {
+        if physical_number <= 1 {
+            0
+        } else {
+            (physical_number - 1) / (ATTACHED_DATA_NODES_COUNT + 1)
+        }
+    }
+
+    /// Recompute the expected and actual GMAC of the MHT node at
+    /// `logical_number`, for reporting in a [`CorruptNode`] once
+    /// [`try_get_mht_node`](Self::try_get_mht_node) has already failed. Mirrors
+    /// that method's decrypt path but, instead of decrypting, recomputes the
+    /// tag from the ciphertext we actually read — the same real-on-disk-tag
+    /// approach used by [`crate::pfs::sys::file::FileInner::scrub`].
+    fn mht_node_gmacs(
+        &mut self,
+        logical_number: u64,
+        encrypt_flags: EncryptFlags,
+    ) -> FsResult<(AeadMac, AeadMac)> {
+        if logical_number == 0 {
+            let physical_number = 1;
+            let mut node = FileNode::new(NodeType::Mht, 0, physical_number, encrypt_flags);
+            node.ciphertext.node_data = self
+                .raw_mhts
+                .get(&physical_number)
+                .ok_or_else(|| eos!(crate::pfs::sys::error::EINVAL))?
+                .clone();
+
+            let mut meta_info = MetadataInfo::default();
+            meta_info.node.metadata.as_mut().copy_from_slice(
+                self.raw_mhts
+                    .get(&0)
+                    .ok_or_else(|| eos!(crate::pfs::sys::error::EINVAL))?
+                    .data
+                    .as_slice(),
+            );
+            let key = self.key_provider.metadata_key(encrypt_flags)?;
+            meta_info.decrypt(&key)?;
+
+            let expected = meta_info.encrypted_plain.mht_gmac;
+            let actual = node
+                .ciphertext_gmac(&meta_info.encrypted_plain.mht_key)
+                .unwrap_or_default();
+            return Ok((expected, actual));
+        }
+
+        let physical_number = 1 + logical_number * (ATTACHED_DATA_NODES_COUNT + 1);
+        let parent_mht_node =
+            self.try_get_mht_node((logical_number - 1) / CHILD_MHT_NODES_COUNT, encrypt_flags)?;
+
+        let mut node = FileNode::new(NodeType::Mht, logical_number, physical_number, encrypt_flags);
+        node.parent = Some(parent_mht_node);
+        node.ciphertext.node_data = self
+            .raw_mhts
+            .get(&physical_number)
+            .ok_or_else(|| eos!(crate::pfs::sys::error::EINVAL))?
+            .clone();
+
+        let gcm_data = node.get_gcm_data()?;
+        let actual = node.ciphertext_gmac(&gcm_data.key).unwrap_or_default();
+        Ok((gcm_data.mac, actual))
+    }
+
+    /// Data-node analogue of [`mht_node_gmacs`](Self::mht_node_gmacs): mirrors
+    /// [`try_decrypt_node`](Self::try_decrypt_node)'s setup but recomputes the
+    /// GMAC from the ciphertext instead of decrypting.
+    fn data_node_gmacs(
+        &mut self,
+        disk_physical_number: u64,
+        node: EncryptedData,
+    ) -> FsResult<(AeadMac, AeadMac)> {
+        let source_offset = disk_physical_number * NODE_SIZE as u64;
+        let (logical_number, physical_number) = Self::get_data_node_numbers(source_offset as usize);
+        ensure!(
+            physical_number == disk_physical_number,
+            eos!(crate::pfs::sys::error::EINVAL)
+        );
+
+        let encrypt_flags = EncryptFlags::UserKey;
+        let mht_node = self.try_get_mht_node(logical_number, encrypt_flags)?;
+
+        let mut data_node = FileNode::new(
+            NodeType::Data,
+            logical_number,
+            physical_number,
+            encrypt_flags,
+        );
+        data_node.parent = Some(mht_node);
+        data_node.ciphertext.node_data = node;
+
+        let gcm_data = data_node.get_gcm_data()?;
+        let actual = data_node.ciphertext_gmac(&gcm_data.key).unwrap_or_default();
+        Ok((gcm_data.mac, actual))
+    }
+
+    /// Walk the whole tree from the root MHT, decrypting every MHT and the
+    /// supplied data nodes, and collect every node whose GMAC fails to verify.
+    pub fn check(&mut self, data_nodes: &HashMap<u64, EncryptedData>) -> FsckReport {
+        let mut report = FsckReport::default();
+
+        let mut mht_logicals: Vec<u64> = self
+            .raw_mhts
+            .keys()
+            .filter(|&&physical| physical != 0)
+            .map(|&physical| Self::mht_logical_from_physical(physical))
+            .collect();
+        mht_logicals.sort_unstable();
+        mht_logicals.dedup();
+
+        for logical in mht_logicals {
+            report.checked += 1;
+            if self.try_get_mht_node(logical, EncryptFlags::UserKey).is_err() {
+                let physical = if logical == 0 {
+                    1
+                } else {
+                    1 + logical * (ATTACHED_DATA_NODES_COUNT + 1)
+                };
+                let (expected_gmac, actual_gmac) = self
+                    .mht_node_gmacs(logical, EncryptFlags::UserKey)
+                    .unwrap_or_default();
+                report.corrupt.push(CorruptNode {
+                    physical_number: physical,
+                    logical_number: logical,
+                    kind: NodeKind::Mht,
+                    expected_gmac,
+                    actual_gmac,
+                });
+            }
+        }
+
+        for (&physical, node) in data_nodes {
+            report.checked += 1;
+            let (logical, _) = Self::get_data_node_numbers((physical * NODE_SIZE as u64) as usize);
+            if self.try_decrypt_node(physical, node.clone()).is_err() {
+                let (expected_gmac, actual_gmac) = self
+                    .data_node_gmacs(physical, node.clone())
+                    .unwrap_or_default();
+                report.corrupt.push(CorruptNode {
+                    physical_number: physical,
+                    logical_number: logical,
+                    kind: NodeKind::Data,
+                    expected_gmac,
+                    actual_gmac,
+                });
+            }
+        }
+
+        report
+    }
+
+    /// Given a set of known-good *plaintext* data nodes (keyed by physical
+    /// number), re-encrypt each under `key`, recompute the GMAC entries of the
+    /// affected MHTs, and re-encrypt the MHT chain up to the root so the
+    /// metadata node is consistent again. Returns the refreshed root MHT MAC.
+    pub fn repair(
+        &mut self,
+        good: &HashMap<u64, [u8; NODE_SIZE]>,
+        key: &AeadKey,
+    ) -> FsResult<AeadMac> {
+        let mut affected_mhts: Vec<u64> = Vec::new();
+        for (&physical, plaintext) in good {
+            let (logical, _) =
+                Self::get_data_node_numbers((physical * NODE_SIZE as u64) as usize);
+            let mht_logical = logical / ATTACHED_DATA_NODES_COUNT;
+            let mht_node = self.try_get_mht_node(mht_logical, EncryptFlags::UserKey)?;
+
+            let mut data_node =
+                FileNode::new(NodeType::Data, logical, physical, EncryptFlags::UserKey);
+            data_node.parent = Some(mht_node);
+            data_node.plaintext.as_mut()[..NODE_SIZE].copy_from_slice(plaintext);
+            data_node.need_writing = true;
+            data_node.new_node = true;
+            data_node.encrypt(key)?;
+
+            if !affected_mhts.contains(&mht_logical) {
+                affected_mhts.push(mht_logical);
+            }
+            // Include every intermediate MHT between this one and the root.
+            let mut ancestor = mht_logical;
+            while ancestor != 0 {
+                ancestor = (ancestor - 1) / CHILD_MHT_NODES_COUNT;
+                if ancestor != 0 && !affected_mhts.contains(&ancestor) {
+                    affected_mhts.push(ancestor);
+                }
+            }
+        }
+
+        // Re-encrypt the affected MHTs, deepest first, so each parent GMAC
+        // entry is refreshed before the parent itself is sealed.
+        affected_mhts.sort_unstable_by(|a, b| b.cmp(a));
+        for mht_logical in affected_mhts {
+            if let Some(mht_node) = self
+                .mhts
+                .get(&(1 + mht_logical * (ATTACHED_DATA_NODES_COUNT + 1)))
+            {
+                mht_node.borrow_mut().encrypt(key)?;
+            }
+        }
+
+        let root = self.try_get_mht_node(0, EncryptFlags::UserKey)?;
+        let mac = root.borrow_mut().encrypt(key)?;
+        Ok(mac)
+    }
+
+    /// Serialize the logical→physical mapping and per-node GMAC table of every
+    /// MHT node that decrypts and verifies to a machine-readable [`DumpTable`]
+    /// for offline inspection; MHT nodes that fail to verify are left out.
+    pub fn dump(&mut self) -> DumpTable {
+        let mut table = DumpTable::default();
+
+        let mut mht_logicals: Vec<u64> = self
+            .raw_mhts
+            .keys()
+            .filter(|&&physical| physical != 0)
+            .map(|&physical| Self::mht_logical_from_physical(physical))
+            .collect();
+        mht_logicals.sort_unstable();
+        mht_logicals.dedup();
+
+        for logical in mht_logicals {
+            let Ok(node) = self.try_get_mht_node(logical, EncryptFlags::UserKey) else {
+                continue;
+            };
+            let physical = if logical == 0 {
+                1
+            } else {
+                1 + logical * (ATTACHED_DATA_NODES_COUNT + 1)
+            };
+            let mac = node
+                .borrow()
+                .get_gcm_data()
+                .map(|gcm| gcm.mac)
+                .unwrap_or_default();
+            table.entries.push(DumpEntry {
+                physical_number: physical,
+                logical_number: logical,
+                kind: NodeKind::Mht,
+                mac,
+            });
+        }
 
-        let data_node = FileNode::build_ref(data_node);
-        data_node
+        table
     }
 }
 
@@ -0,0 +1,123 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License..
+
+use super::{HostFile, HostFs};
+use crate::pfs::sys::error::FsResult;
+use crate::pfs::sys::node::NODE_SIZE;
+use std::path::{Path, PathBuf};
+
+/// 2 GiB (2^31 bytes) per component, expressed in `NODE_SIZE` blocks. Keeps
+/// each host file under a 4 GiB single-file limit.
+pub const DEFAULT_BLOCKS_PER_FILE: u64 = (2 * 1024 * 1024 * 1024) / NODE_SIZE as u64;
+
+/// A [`HostFs`] that transparently spans several host files, each capped at a
+/// fixed block count, presenting one continuous block address space. This
+/// mirrors the split-file backing of the disc-image formats and is needed
+/// where the host filesystem imposes a per-file size limit or the volume must
+/// be moved in chunks.
+///
+/// Component files follow the `name.000`, `name.001`, … naming convention.
+/// Components that already exist are opened at construction; a missing one is
+/// created or opened the first time a block in its range is touched.
+pub struct SplitHostFile {
+    base: PathBuf,
+    readonly: bool,
+    blocks_per_file: u64,
+    components: Vec<Option<HostFile>>,
+}
+
+impl SplitHostFile {
+    /// Open (or, when not `readonly`, lazily create) a split store rooted at
+    /// `base`, discovering any already-present `base.NNN` components.
+    pub fn open(base: &Path, readonly: bool) -> FsResult<SplitHostFile> {
+        Self::with_capacity(base, readonly, DEFAULT_BLOCKS_PER_FILE)
+    }
+
+    pub fn with_capacity(
+        base: &Path,
+        readonly: bool,
+        blocks_per_file: u64,
+    ) -> FsResult<SplitHostFile> {
+        assert!(blocks_per_file > 0);
+        let mut components: Vec<Option<HostFile>> = Vec::new();
+        // Discover existing components by naming convention.
+        let mut index = 0usize;
+        while component_path(base, index).exists() {
+            components.push(Some(HostFile::open(&component_path(base, index), readonly)?));
+            index += 1;
+        }
+        Ok(SplitHostFile {
+            base: base.to_path_buf(),
+            readonly,
+            blocks_per_file,
+            components,
+        })
+    }
+
+    fn locate(&self, number: u64) -> (usize, u64) {
+        (
+            (number / self.blocks_per_file) as usize,
+            number % self.blocks_per_file,
+        )
+    }
+
+    fn component(&mut self, index: usize) -> FsResult<&mut HostFile> {
+        if index >= self.components.len() {
+            self.components.resize_with(index + 1, || None);
+        }
+        if self.components[index].is_none() {
+            let file = HostFile::open(&component_path(&self.base, index), self.readonly)?;
+            self.components[index] = Some(file);
+        }
+        Ok(self.components[index].as_mut().unwrap())
+    }
+}
+
+fn component_path(base: &Path, index: usize) -> PathBuf {
+    let mut name = base.as_os_str().to_os_string();
+    name.push(format!(".{:03}", index));
+    PathBuf::from(name)
+}
+
+impl HostFs for SplitHostFile {
+    fn read(&mut self, number: u64, node: &mut dyn AsMut<[u8]>) -> FsResult {
+        let (index, local) = self.locate(number);
+        self.component(index)?.read(local, node)
+    }
+
+    fn write(&mut self, number: u64, node: &dyn AsRef<[u8]>) -> FsResult {
+        let (index, local) = self.locate(number);
+        self.component(index)?.write(local, node)
+    }
+
+    fn flush(&mut self) -> FsResult {
+        // Fan the flush out to every open component.
+        for component in self.components.iter_mut().flatten() {
+            component.flush()?;
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> FsResult<usize> {
+        // Sum across all open components.
+        let mut total = 0usize;
+        for component in self.components.iter().flatten() {
+            total += component.len()?;
+        }
+        Ok(total)
+    }
+}
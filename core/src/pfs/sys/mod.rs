@@ -86,9 +86,19 @@ impl<D: BlockSet> SgxFile<D> {
         opts: &OpenOptions,
         encrypt_mode: &EncryptMode,
         cache_size: Option<usize>,
+        workers: usize,
+        layout: Option<file_imp::LayoutConfig>,
     ) -> FsResult<SgxFile<D>> {
-        ProtectedFile::open(disk, path, &opts.0, &encrypt_mode.into(), cache_size)
-            .map(|f| SgxFile { file: Box::new(f) })
+        ProtectedFile::open(
+            disk,
+            path,
+            &opts.0,
+            &encrypt_mode.into(),
+            cache_size,
+            workers,
+            layout,
+        )
+        .map(|f| SgxFile { file: Box::new(f) })
     }
 
     pub fn create(
@@ -97,9 +107,19 @@ impl<D: BlockSet> SgxFile<D> {
         opts: &OpenOptions,
         encrypt_mode: &EncryptMode,
         cache_size: Option<usize>,
+        workers: usize,
+        layout: Option<file_imp::LayoutConfig>,
     ) -> FsResult<SgxFile<D>> {
-        ProtectedFile::create(disk, path, &opts.0, &encrypt_mode.into(), cache_size)
-            .map(|f| SgxFile { file: Box::new(f) })
+        ProtectedFile::create(
+            disk,
+            path,
+            &opts.0,
+            &encrypt_mode.into(),
+            cache_size,
+            workers,
+            layout,
+        )
+        .map(|f| SgxFile { file: Box::new(f) })
     }
 
     #[inline]
@@ -173,6 +193,24 @@ impl<D: BlockSet> SgxFile<D> {
         self.file.get_metadata_mac()
     }
 
+    #[inline]
+    pub fn metadata(&self) -> FsResult<file_imp::FileMetadata> {
+        self.file.metadata()
+    }
+
+    /// Unwrap and return the per-file node key from the metadata node.
+    #[inline]
+    pub fn export_metadata_key(&self) -> FsResult<AeadKey> {
+        self.file.export_metadata_key()
+    }
+
+    /// Re-wrap the per-file node key under `new_root`, updating the metadata
+    /// MAC. The data nodes are left untouched.
+    #[inline]
+    pub fn import_metadata_key(&self, new_root: AeadKey) -> FsResult<()> {
+        self.file.import_metadata_key(new_root)
+    }
+
     #[inline]
     pub fn rename<P: AsRef<str>, Q: AsRef<str>>(&self, old_name: P, new_name: Q) -> FsResult<()> {
         self.file.rename(old_name, new_name)
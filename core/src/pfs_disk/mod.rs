@@ -1,10 +1,12 @@
 pub use self::open_options::OpenOptions;
-use crate::layers::disk::bio::{BioReq, BioType};
+use crate::layers::disk::bio::{BioReq, BioType, BlockDevice, BioSubmission};
+use std::sync::mpsc::{self, Sender};
+use std::thread::JoinHandle;
 use crate::os::Mutex;
 use crate::os::SeekFrom;
 use crate::pfs::fs::SgxFile as PfsFile;
 use crate::pfs::sys::error::OsError;
-use crate::{prelude::*, AeadKey, BlockSet, BufMut};
+use crate::{prelude::*, AeadKey, AeadMac, BlockSet, Buf, BufMut};
 use crate::{BufRef, Errno};
 
 mod open_options;
@@ -13,52 +15,346 @@ mod open_options;
 /// System Library (SGX-PFS).
 ///
 /// This type of disks is considered (relatively) secure.
+///
+/// The shared state lives in [`PfsDiskInner`] behind an `Arc` so a
+/// [`DiskWorker`] can hold its own clone and dispatch requests without
+/// borrowing from (or outliving) this handle.
 pub struct PfsDisk<D: BlockSet> {
+    inner: Arc<PfsDiskInner<D>>,
+    worker: DiskWorker<D>,
+}
+
+struct PfsDiskInner<D: BlockSet> {
     file: Mutex<PfsFile<D>>,
     path: String,
     total_blocks: usize,
     can_read: bool,
     can_write: bool,
+    journal: Mutex<WriteIntentJournal>,
+}
+
+/// Lightweight metadata snapshot returned by [`PfsDisk::stat`].
+#[derive(Clone, Debug)]
+pub struct DiskStat {
+    /// Logical usable block count (`total_data_blocks`).
+    pub total_data_blocks: usize,
+    /// Physical size of the backing PFS file, in bytes.
+    pub physical_size: u64,
+    /// Current metadata MAC.
+    pub mac: AeadMac,
+    /// Nanosecond access/modify/change timestamps from the encrypted header.
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+/// A fixed-size write-intent record. `committed` flips to true only after the
+/// data node flush for `block_addr` succeeds, so a record left uncommitted on
+/// reopen marks a block that may have been torn mid-write.
+#[derive(Copy, Clone, Debug, Default)]
+struct IntentRecord {
+    block_addr: u64,
+    generation: u64,
+    committed: bool,
+}
+
+/// Number of intent slots kept in the ring. The region has this fixed size,
+/// independent of the total block count; as the ring wraps, `begin` reuses
+/// the oldest slot whether or not its intent was committed.
+const JOURNAL_CAPACITY: usize = 256;
+
+/// On-disk layout of one [`IntentRecord`]: `block_addr` (u64 LE) +
+/// `generation` (u64 LE) + `committed` (one byte, 0 or 1).
+const RECORD_SIZE: usize = 8 + 8 + 1;
+
+/// On-disk header ahead of the ring: `head` (u64 LE) + `generation` (u64 LE).
+const JOURNAL_HEADER_SIZE: usize = 8 + 8;
+
+/// Total size in bytes of the persisted journal region.
+const JOURNAL_REGION_SIZE: usize = JOURNAL_HEADER_SIZE + JOURNAL_CAPACITY * RECORD_SIZE;
+
+impl IntentRecord {
+    fn encode(&self, out: &mut [u8]) {
+        out[0..8].copy_from_slice(&self.block_addr.to_le_bytes());
+        out[8..16].copy_from_slice(&self.generation.to_le_bytes());
+        out[16] = self.committed as u8;
+    }
+
+    fn decode(raw: &[u8]) -> Self {
+        let mut block_addr = [0u8; 8];
+        block_addr.copy_from_slice(&raw[0..8]);
+        let mut generation = [0u8; 8];
+        generation.copy_from_slice(&raw[8..16]);
+        Self {
+            block_addr: u64::from_le_bytes(block_addr),
+            generation: u64::from_le_bytes(generation),
+            committed: raw[16] != 0,
+        }
+    }
+}
+
+/// A bounded ring of write intents, persisted to a reserved region of the
+/// backing PFS file (see [`PfsDiskInner::journal_region_offset`]) so a
+/// process crash does not take the only copy of the journal down with it. The
+/// ring is re-read from that region on [`PfsDisk::open`], written fresh on
+/// [`PfsDisk::create`], and re-written after every `begin`/`commit` pair.
+#[derive(Debug)]
+struct WriteIntentJournal {
+    ring: Vec<IntentRecord>,
+    head: usize,
+    generation: u64,
+}
+
+impl WriteIntentJournal {
+    fn new() -> Self {
+        Self {
+            ring: vec![IntentRecord::default(); JOURNAL_CAPACITY],
+            head: 0,
+            generation: 0,
+        }
+    }
+
+    /// Record the intent to write `block_addr`, returning the slot index so the
+    /// caller can mark it committed once the node flush succeeds.
+    fn begin(&mut self, block_addr: u64) -> usize {
+        self.generation += 1;
+        let idx = self.head;
+        self.ring[idx] = IntentRecord {
+            block_addr,
+            generation: self.generation,
+            committed: false,
+        };
+        self.head = (self.head + 1) % JOURNAL_CAPACITY;
+        idx
+    }
+
+    fn commit(&mut self, idx: usize) {
+        self.ring[idx].committed = true;
+    }
+
+    /// Block addresses of every still-uncommitted intent, most recent first.
+    fn uncommitted(&self) -> Vec<usize> {
+        let mut pending: Vec<&IntentRecord> = self
+            .ring
+            .iter()
+            .filter(|r| r.generation != 0 && !r.committed)
+            .collect();
+        pending.sort_unstable_by(|a, b| b.generation.cmp(&a.generation));
+        pending.iter().map(|r| r.block_addr as usize).collect()
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; JOURNAL_REGION_SIZE];
+        buf[0..8].copy_from_slice(&(self.head as u64).to_le_bytes());
+        buf[8..JOURNAL_HEADER_SIZE].copy_from_slice(&self.generation.to_le_bytes());
+        for (i, record) in self.ring.iter().enumerate() {
+            let base = JOURNAL_HEADER_SIZE + i * RECORD_SIZE;
+            record.encode(&mut buf[base..base + RECORD_SIZE]);
+        }
+        buf
+    }
+
+    fn decode(raw: &[u8]) -> Self {
+        let mut head = [0u8; 8];
+        head.copy_from_slice(&raw[0..8]);
+        let mut generation = [0u8; 8];
+        generation.copy_from_slice(&raw[8..JOURNAL_HEADER_SIZE]);
+        let ring = (0..JOURNAL_CAPACITY)
+            .map(|i| {
+                let base = JOURNAL_HEADER_SIZE + i * RECORD_SIZE;
+                IntentRecord::decode(&raw[base..base + RECORD_SIZE])
+            })
+            .collect();
+        Self {
+            ring,
+            head: u64::from_le_bytes(head) as usize,
+            generation: u64::from_le_bytes(generation),
+        }
+    }
 }
 
 // Safety. PfsFile does not implement Send, but it is safe to do so.
-unsafe impl<D: BlockSet> Send for PfsDisk<D> {}
+unsafe impl<D: BlockSet> Send for PfsDiskInner<D> {}
 // Safety. PfsFile does not implement Sync but it is safe to do so.
-unsafe impl<D: BlockSet> Sync for PfsDisk<D> {}
+unsafe impl<D: BlockSet> Sync for PfsDiskInner<D> {}
 
 // The first 3KB file data of PFS are stored in the metadata node. All remaining
 // file data are stored in nodes of 4KB. We need to consider this internal
 // offset so that our block I/O are aligned with the PFS internal node boundaries.
 const PFS_INNER_OFFSET: usize = 3 * 1024;
 
-impl<D: BlockSet> PfsDisk<D> {
+impl<D: BlockSet + 'static> PfsDisk<D> {
     /// Open a disk backed by an existing PFS file on the host.
     pub fn open(disk: D, root_key: AeadKey, path: Option<&str>) -> Result<Self> {
         let path = path.unwrap_or("pfsdisk");
-        OpenOptions::new()
+        let inner = OpenOptions::new()
             .read(true)
             .write(true)
-            .open(path, disk, root_key)
+            .open(path, disk, root_key)?;
+        let inner = Arc::new(inner);
+        // Recover whatever write-intent journal the previous session persisted,
+        // so an uncommitted intent from a crash is not lost along with it.
+        inner.load_journal()?;
+        Ok(Self {
+            worker: DiskWorker::spawn(inner.clone()),
+            inner,
+        })
     }
 
     /// Open a disk by opening or creating a PFS file on the give path.
     pub fn create(disk: D, root_key: AeadKey, path: Option<&str>) -> Result<Self> {
         let path = path.unwrap_or("pfsdisk");
-        let total_blocks = PfsDisk::<D>::total_data_blocks(disk.nblocks());
-        OpenOptions::new()
+        let total_blocks = PfsDiskInner::<D>::total_data_blocks(disk.nblocks());
+        let inner = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
             .total_blocks(total_blocks)
-            .open(path, disk, root_key)
+            .open(path, disk, root_key)?;
+        // Reserve and zero-initialize the journal region up front.
+        inner.persist_journal()?;
+        let inner = Arc::new(inner);
+        Ok(Self {
+            worker: DiskWorker::spawn(inner.clone()),
+            inner,
+        })
     }
 
     /// Returns the PFS file on the host Linux.
     pub fn path(&self) -> &str {
+        self.inner.path()
+    }
+
+    /// Lightweight stat of the disk: usable block count, on-host physical
+    /// size, current metadata MAC, and the encrypted-header timestamps.
+    ///
+    /// Integrity-monitoring tools can compare two replicas by `(mac, mtime)`
+    /// before falling back to a full block scan.
+    pub fn stat(&self) -> Result<DiskStat> {
+        self.inner.stat()
+    }
+
+    /// Export the unwrapped per-file key of the underlying PFS file.
+    ///
+    /// For an auto-key file sealed to an enclave's `KeyPolicy` this returns the
+    /// derived file key so the disk can be migrated to another machine and
+    /// re-imported there under `EncryptMode::EncryptUserKey`.
+    pub fn export_key(&self) -> Result<AeadKey> {
+        self.inner.export_key()
+    }
+
+    /// Rotate the root key that wraps the metadata key-encryption-key.
+    ///
+    /// The stored per-file node key is unwrapped with the current root key and
+    /// re-wrapped under `new_root`; the metadata MAC is updated and flushed.
+    /// The 4KB data nodes are *not* rewritten — only the key-encryption-key in
+    /// the metadata changes — so rotation is O(1) in file size.
+    pub fn rotate_key(&self, new_root: AeadKey) -> Result<()> {
+        self.inner.rotate_key(new_root)
+    }
+
+    pub fn read(&self, addr: usize, buf: BufMut) -> Result<()> {
+        self.inner.read(addr, buf)
+    }
+
+    pub fn write(&self, addr: usize, buf: BufRef) -> Result<()> {
+        self.inner.write(addr, buf)
+    }
+
+    /// Replay the write-intent journal and return the block address of every
+    /// intent that was never committed, so the caller can re-issue them. Node
+    /// MACs are not checked. A crash mid-write leaves an uncommitted intent; a
+    /// clean shutdown leaves none.
+    pub fn recover(&self) -> Result<Vec<usize>> {
+        self.inner.recover()
+    }
+
+    /// Read a contiguous run of blocks starting at `addr` in one locked
+    /// section, issuing a single `read_at` over the whole span so the PFS
+    /// node cache amortizes the per-node decrypt+MAC-verify across the run,
+    /// then scattering the result into `bufs`.
+    pub fn read_blocks(&self, addr: usize, bufs: &mut [BufMut]) -> Result<()> {
+        self.inner.read_blocks(addr, bufs)
+    }
+
+    /// Write a contiguous run of blocks starting at `addr` in one locked
+    /// section: gather `bufs` into a single span and issue one `write_at`,
+    /// avoiding a seek and a separate node encrypt per block.
+    pub fn write_blocks(&self, addr: usize, bufs: &[BufRef]) -> Result<()> {
+        self.inner.write_blocks(addr, bufs)
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.inner.sync()
+    }
+}
+
+impl<D: BlockSet> PfsDiskInner<D> {
+    /// Byte offset, within the backing PFS file, of the reserved write-intent
+    /// journal region. It lives just past the usable data blocks — the 3/16 of
+    /// the backing disk that [`total_data_blocks`](Self::total_data_blocks)
+    /// deliberately leaves unaddressed by the logical block space.
+    fn journal_region_offset(&self) -> u64 {
+        self.total_blocks as u64 * BLOCK_SIZE as u64 + PFS_INNER_OFFSET as u64
+    }
+
+    /// Persist the in-memory write-intent journal to its reserved region and
+    /// fsync, so it survives a crash immediately after this call returns.
+    fn persist_journal(&self) -> Result<()> {
+        let encoded = self.journal.lock().encode();
+        let offset = self.journal_region_offset();
+        let mut file = self.file.lock();
+        file.write_at(&encoded, offset).map_err(|e| e.to_errno())?;
+        file.flush().map_err(|e| e.to_errno())
+    }
+
+    /// Replace the in-memory journal with whatever is stored in the reserved
+    /// region. A read failure (e.g. a freshly created file with nothing
+    /// written there yet) leaves the journal at its empty default.
+    fn load_journal(&self) -> Result<()> {
+        let offset = self.journal_region_offset();
+        let mut raw = vec![0u8; JOURNAL_REGION_SIZE];
+        let read = {
+            let file = self.file.lock();
+            file.read_at(&mut raw, offset)
+        };
+        if read.is_ok() {
+            *self.journal.lock() = WriteIntentJournal::decode(&raw);
+        }
+        Ok(())
+    }
+
+    fn path(&self) -> &str {
         &self.path
     }
 
-    pub fn read(&self, addr: usize, mut buf: BufMut) -> Result<()> {
+    fn stat(&self) -> Result<DiskStat> {
+        let file = self.file.lock();
+        let mac = file.get_mac().map_err(|e| e.to_errno())?;
+        let physical_size = file.file_size().map_err(|e| e.to_errno())?;
+        let meta = file.metadata().map_err(|e| e.to_errno())?;
+        Ok(DiskStat {
+            total_data_blocks: self.total_blocks,
+            physical_size,
+            mac,
+            atime: meta.atime,
+            mtime: meta.mtime,
+            ctime: meta.ctime,
+        })
+    }
+
+    fn export_key(&self) -> Result<AeadKey> {
+        let file = self.file.lock();
+        file.export_metadata_key().map_err(|e| e.to_errno())
+    }
+
+    fn rotate_key(&self, new_root: AeadKey) -> Result<()> {
+        let mut file = self.file.lock();
+        file.import_metadata_key(new_root).map_err(|e| e.to_errno())?;
+        file.flush().map_err(|e| e.to_errno())
+    }
+
+    fn read(&self, addr: usize, mut buf: BufMut) -> Result<()> {
         if !self.can_read {
             return_errno_with_msg!(Errno::IoFailed, "read is not allowed")
         }
@@ -71,23 +367,112 @@ impl<D: BlockSet> PfsDisk<D> {
         Ok(())
     }
 
-    pub fn write(&self, addr: usize, buf: BufRef) -> Result<()> {
+    fn write(&self, addr: usize, buf: BufRef) -> Result<()> {
         if !self.can_write {
             return_errno_with_msg!(Errno::IoFailed, "write is not allowed")
         }
         self.validate_range(addr)?;
+        // Record the write intent before touching the data node, and persist
+        // it so the intent survives a crash; mark it committed, and persist
+        // again, only after the node flush succeeds.
+        let slot = self.journal.lock().begin(addr as u64);
+        self.persist_journal()?;
         let offset = addr * BLOCK_SIZE + PFS_INNER_OFFSET;
         let mut file = self.file.lock();
         file.seek(SeekFrom::Start(offset as u64)).unwrap();
         file.write(buf.as_slice()).unwrap();
+        file.flush().map_err(|e| e.to_errno())?;
+        drop(file);
+        self.journal.lock().commit(slot);
+        self.persist_journal()?;
         Ok(())
     }
 
-    pub fn sync(&self) -> Result<()> {
+    fn recover(&self) -> Result<Vec<usize>> {
+        // The journal's commit flag is the only authoritative crash signal
+        // here: a crash mid-write can leave a node with a valid-but-stale
+        // MAC, so a block that was mid-flight when we crashed still reads
+        // back and verifies cleanly. Re-filtering the candidates by whether
+        // `file.read` happens to succeed would silently drop exactly the
+        // blocks this journal exists to surface, so report the uncommitted
+        // list as-is and let the caller decide how to repair each one.
+        let candidates = self.journal.lock().uncommitted();
+        Ok(candidates
+            .into_iter()
+            .filter(|&addr| addr < self.total_blocks)
+            .collect())
+    }
+
+    fn read_blocks(&self, addr: usize, bufs: &mut [BufMut]) -> Result<()> {
+        if !self.can_read {
+            return_errno_with_msg!(Errno::IoFailed, "read is not allowed")
+        }
+        let nblocks = bufs.len();
+        if nblocks == 0 {
+            return Ok(());
+        }
+        self.validate_range(addr + nblocks - 1)?;
+
+        let offset = addr * BLOCK_SIZE + PFS_INNER_OFFSET;
+        let mut staging = vec![0u8; nblocks * BLOCK_SIZE];
+        let mut file = self.file.lock();
+        file.read_at(&mut staging, offset as u64).unwrap();
+        drop(file);
+
+        for (i, buf) in bufs.iter_mut().enumerate() {
+            let start = i * BLOCK_SIZE;
+            buf.as_mut_slice()
+                .copy_from_slice(&staging[start..start + BLOCK_SIZE]);
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, addr: usize, bufs: &[BufRef]) -> Result<()> {
+        if !self.can_write {
+            return_errno_with_msg!(Errno::IoFailed, "write is not allowed")
+        }
+        let nblocks = bufs.len();
+        if nblocks == 0 {
+            return Ok(());
+        }
+        self.validate_range(addr + nblocks - 1)?;
+
+        let mut staging = vec![0u8; nblocks * BLOCK_SIZE];
+        for (i, buf) in bufs.iter().enumerate() {
+            let start = i * BLOCK_SIZE;
+            staging[start..start + BLOCK_SIZE].copy_from_slice(buf.as_slice());
+        }
+
+        let offset = addr * BLOCK_SIZE + PFS_INNER_OFFSET;
+        let mut file = self.file.lock();
+        file.write_at(&staging, offset as u64).unwrap();
+        file.flush().map_err(|e| e.to_errno())?;
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
         let mut file = self.file.lock();
         file.flush().map_err(|e| e.to_errno())
     }
 
+    /// Dispatch one request, coalescing its buffers into the single
+    /// node-aligned seek+run that `do_read`/`do_write` already perform, and
+    /// complete it in place. Called from whichever thread pulled the request
+    /// off the [`DiskWorker`] queue.
+    fn dispatch(&self, req: &Arc<BioReq>) {
+        let res = match req.type_() {
+            BioType::Read => self.do_read(req),
+            BioType::Write => self.do_write(req),
+            BioType::Flush => self.do_flush(),
+        };
+        let resp = res.map_err(|e| e.errno());
+        // Safety: the request has been fully processed above, so completing it
+        // exactly once here transfers the response to the waiter.
+        unsafe {
+            req.complete(resp);
+        }
+    }
+
     fn do_read(&self, req: &Arc<BioReq>) -> Result<()> {
         if !self.can_read {
             return_errno_with_msg!(Errno::IoFailed, "read is not allowed")
@@ -116,9 +501,19 @@ impl<D: BlockSet> PfsDisk<D> {
             return_errno_with_msg!(Errno::IoFailed, "write is not allowed")
         }
 
+        let begin_block = req.addr();
         let (offset, _) = self.get_range_in_bytes(&req)?;
         let offset = offset + PFS_INNER_OFFSET;
 
+        // One intent per block in the request; committed together after flush.
+        let slots: Vec<usize> = {
+            let mut journal = self.journal.lock();
+            (0..req.nblocks())
+                .map(|i| journal.begin((begin_block + i) as u64))
+                .collect()
+        };
+        self.persist_journal()?;
+
         let mut file = self.file.lock();
         file.seek(SeekFrom::Start(offset as u64)).unwrap();
         req.access_bufs_with(|bufs| {
@@ -129,8 +524,18 @@ impl<D: BlockSet> PfsDisk<D> {
                 debug_assert!(write_len == buf.len());
             }
         });
+        file.flush().map_err(|e| e.to_errno())?;
         drop(file);
 
+        // The nodes are durable: mark the whole group committed.
+        {
+            let mut journal = self.journal.lock();
+            for slot in slots {
+                journal.commit(slot);
+            }
+        }
+        self.persist_journal()?;
+
         Ok(())
     }
 
@@ -165,38 +570,76 @@ impl<D: BlockSet> PfsDisk<D> {
         let end_offset = end_block * BLOCK_SIZE;
         Ok((begin_offset, end_offset))
     }
+
     fn total_data_blocks(total_blocks: usize) -> usize {
         total_blocks * 13 / 16
     }
 }
 
-// impl BlockDevice for PfsDisk {
-//     fn total_blocks(&self) -> usize {
-//         self.total_blocks
-//     }
-
-//     fn submit(&self, req: Arc<BioReq>) -> BioSubmission {
-//         // Update the status of req to submittted
-//         let submission = BioSubmission::new(req);
-
-//         let req = submission.req();
-//         let type_ = req.type_();
-//         let res = match type_ {
-//             BioType::Read => self.do_read(req),
-//             BioType::Write => self.do_write(req),
-//             BioType::Flush => self.do_flush(),
-//         };
-
-//         // Update the status of req to completed and set the response
-//         let resp = res.map_err(|e| e.errno());
-//         unsafe {
-//             req.complete(resp);
-//         }
-
-//         submission
-//     }
-
-impl<D: BlockSet> Drop for PfsDisk<D> {
+impl<D: BlockSet> BlockDevice for PfsDisk<D> {
+    fn total_blocks(&self) -> usize {
+        self.inner.total_blocks
+    }
+
+    fn submit(&self, req: Arc<BioReq>) -> BioSubmission {
+        // Hand the request to the worker's queue instead of dispatching
+        // inline, so a slow PFS crypto operation on one request does not
+        // block the next caller from enqueuing theirs.
+        self.worker.submit(req)
+    }
+}
+
+/// A dedicated worker thread draining a submission queue, so many `BioReq`s
+/// can be fired without blocking the producer on the `PfsFile` mutex.
+/// [`PfsDisk`] owns exactly one of these and routes every [`BlockDevice::submit`]
+/// call through it; callers await completion via `req.complete(resp)` on each
+/// request.
+struct DiskWorker<D: BlockSet> {
+    tx: Option<Sender<Arc<BioReq>>>,
+    handle: Option<JoinHandle<()>>,
+    _inner: Arc<PfsDiskInner<D>>,
+}
+
+impl<D: BlockSet + 'static> DiskWorker<D> {
+    /// Spawn a worker bound to `inner`.
+    fn spawn(inner: Arc<PfsDiskInner<D>>) -> Self {
+        let (tx, rx) = mpsc::channel::<Arc<BioReq>>();
+        let worker_inner = inner.clone();
+        let handle = std::thread::spawn(move || {
+            // Drain until the sender is dropped; each request is dispatched and
+            // completed on this thread, overlapping with producers.
+            while let Ok(req) = rx.recv() {
+                worker_inner.dispatch(&req);
+            }
+        });
+        Self {
+            tx: Some(tx),
+            handle: Some(handle),
+            _inner: inner,
+        }
+    }
+
+    /// Enqueue a request for asynchronous processing.
+    fn submit(&self, req: Arc<BioReq>) -> BioSubmission {
+        let submission = BioSubmission::new(req);
+        // The queue owns a clone of the Arc until the worker completes it.
+        let _ = self.tx.as_ref().unwrap().send(submission.req().clone());
+        submission
+    }
+}
+
+impl<D: BlockSet> Drop for DiskWorker<D> {
+    fn drop(&mut self) {
+        // Close the queue and join so in-flight requests finish before the
+        // backing disk is dropped.
+        self.tx.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl<D: BlockSet> Drop for PfsDiskInner<D> {
     fn drop(&mut self) {
         let mut file = self.file.lock();
         file.flush().unwrap();
@@ -208,8 +651,8 @@ impl<D: BlockSet> Drop for PfsDisk<D> {
 impl<D: BlockSet> fmt::Debug for PfsDisk<D> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("PfsDisk")
-            .field("path", &self.path)
-            .field("total_blocks", &self.total_blocks)
+            .field("path", &self.inner.path)
+            .field("total_blocks", &self.inner.total_blocks)
             .finish()
     }
 }
@@ -270,4 +713,36 @@ mod test {
             assert_eq!(read_buf.as_slice(), &[i as u8; BLOCK_SIZE]);
         }
     }
+
+    #[test]
+    fn vectored_read_write() {
+        init_logger();
+        let root_key = AeadKey::default();
+        let disk = MemDisk::create(11000).unwrap();
+        let disk = PfsDisk::create(disk, root_key, None).unwrap();
+
+        let block_count = 8000;
+        // One vectored write over the whole contiguous run instead of 8000
+        // separate seek+encrypt calls.
+        let data: Vec<Vec<u8>> = (0..block_count).map(|i| vec![i as u8; BLOCK_SIZE]).collect();
+        let bufs: Vec<BufRef> = data
+            .iter()
+            .map(|b| BufRef::try_from(b.as_slice()).unwrap())
+            .collect();
+        disk.write_blocks(0, &bufs).unwrap();
+
+        let mut out = Buf::alloc(block_count).unwrap();
+        let mut mut_bufs: Vec<BufMut> = out
+            .as_mut_slice()
+            .chunks_mut(BLOCK_SIZE)
+            .map(|c| BufMut::try_from(c).unwrap())
+            .collect();
+        disk.read_blocks(0, &mut mut_bufs).unwrap();
+        drop(mut_bufs);
+
+        for i in 0..block_count {
+            let start = i * BLOCK_SIZE;
+            assert_eq!(&out.as_slice()[start..start + BLOCK_SIZE], &vec![i as u8; BLOCK_SIZE][..]);
+        }
+    }
 }